@@ -0,0 +1,64 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+// Data that was not committed in this time is dropped, e.g. when client failed after push
+const BUFFER_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+struct BufferedData {
+    data: Vec<u8>,
+    received: Instant,
+}
+
+// Pushed data waiting for commit from primary, stored under data id chosen by client
+#[derive(Debug, Default)]
+pub struct DataBuffer {
+    buffers: Mutex<HashMap<String, BufferedData>>,
+}
+
+impl DataBuffer {
+    pub fn new() -> Self {
+        DataBuffer::default()
+    }
+
+    pub fn insert(&self, data_id: String, data: Vec<u8>) {
+        let mut buffers = self.buffers.lock().unwrap();
+
+        buffers.retain(|_, buffered| buffered.received.elapsed() < BUFFER_TIMEOUT);
+
+        buffers.insert(
+            data_id,
+            BufferedData {
+                data,
+                received: Instant::now(),
+            },
+        );
+    }
+
+    // Data is removed from buffer once it is committed
+    pub fn take(&self, data_id: &str) -> Option<Vec<u8>> {
+        self.buffers
+            .lock()
+            .unwrap()
+            .remove(data_id)
+            .map(|buffered| buffered.data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_data_should_be_taken_once() {
+        let buffer = DataBuffer::new();
+
+        buffer.insert("id".to_string(), vec![1, 2, 3]);
+
+        assert_eq!(buffer.take("id"), Some(vec![1, 2, 3]));
+        assert_eq!(buffer.take("id"), None);
+    }
+}
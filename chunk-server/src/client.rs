@@ -1,10 +1,14 @@
 use std::{sync::Arc, time::Duration};
 
-use common::master_server::{chunk_service_client::ChunkServiceClient, HeartbeatRequest};
-use tokio::time::{interval, Interval};
+use common::{
+    master_server::{chunk_service_client::ChunkServiceClient, HeartbeatRequest, StoredChunk},
+    time::{from_unix_millis, to_unix_millis},
+};
+use tokio::time::interval;
 use tonic::Request;
 use tracing::{error, info};
 
+use crate::leases::Leases;
 use crate::storage::Storage;
 
 pub struct Client {
@@ -12,6 +16,7 @@ pub struct Client {
     master_address: String,
     interval: u64,
     storage: Arc<Storage>,
+    leases: Arc<Leases>,
 }
 
 impl Client {
@@ -21,6 +26,7 @@ impl Client {
         master_port: u16,
         interval: u64,
         storage: Arc<Storage>,
+        leases: Arc<Leases>,
     ) -> Client {
         let master_address = format!("http://{}:{}", master_host, master_port);
 
@@ -29,6 +35,7 @@ impl Client {
             master_address,
             interval,
             storage,
+            leases,
         }
     }
 
@@ -36,6 +43,7 @@ impl Client {
         let master_address = self.master_address.clone();
         let server_address = self.server_address.clone();
         let storage = self.storage.clone();
+        let leases = self.leases.clone();
         let mut interval = interval(Duration::from_secs(self.interval));
 
         tokio::spawn(async move {
@@ -48,14 +56,29 @@ impl Client {
 
                 let used = storage.get_used_storage();
                 let available = storage.get_available_storage();
-                let chunk_handles = storage.get_chunk_handles();
+                let chunks = storage
+                    .get_chunk_handles()
+                    .into_iter()
+                    .map(|chunk_handle| StoredChunk {
+                        version: storage.get_chunk_version(chunk_handle),
+                        size: storage.get_chunk_size(chunk_handle).unwrap_or(0),
+                        modified_at: storage
+                            .get_chunk_modified(chunk_handle)
+                            .map_or(0, to_unix_millis),
+                        chunk_handle,
+                    })
+                    .collect();
+                let leased_chunks = leases.get_leased_chunks();
+                let corrupted_chunks = storage.get_corrupted_chunks();
                 let server_address = server_address.clone();
 
                 let request = Request::new(HeartbeatRequest {
                     server_address,
                     used,
                     available,
-                    chunk_handles,
+                    chunks,
+                    leased_chunks,
+                    corrupted_chunks,
                 });
 
                 // TODO: try_connect()
@@ -64,10 +87,38 @@ impl Client {
                     .expect("Client should connect with master server.");
 
                 match client.heartbeat(request).await {
-                    Ok(response) => info!(
-                        "Heartbeat sent. Resonse.to_delete len: {}",
-                        response.into_inner().to_delete.len()
-                    ),
+                    Ok(response) => {
+                        let response = response.into_inner();
+
+                        info!(
+                            "Heartbeat sent. Resonse.to_delete len: {}",
+                            response.to_delete.len()
+                        );
+
+                        // Chunks of purged files, stale and corrupted replicas
+                        let chunk_storage = storage.clone();
+                        let to_delete = response.to_delete;
+
+                        let deleted = tokio::task::spawn_blocking(move || {
+                            for chunk_handle in to_delete {
+                                if let Err(e) = chunk_storage.delete_chunk(chunk_handle) {
+                                    error!(
+                                        "Failed to delete chunk: {}, because: {}",
+                                        chunk_handle, e
+                                    );
+                                }
+                            }
+                        })
+                        .await;
+
+                        if let Err(e) = deleted {
+                            error!("Deletion of chunks panicked: {}", e);
+                        }
+
+                        for lease in response.extended_leases {
+                            leases.extend(lease.chunk_handle, from_unix_millis(lease.expiration));
+                        }
+                    }
                     Err(e) => error!("Failed to send heartbeat: {}", e),
                 }
             }
@@ -5,6 +5,14 @@ use serde::Deserialize;
 pub struct Settings {
     pub master_port: u16,
     pub master_host: String,
+    // Seconds between heartbeats, should be shorter than lease duration on master
+    pub heartbeat_interval: u64,
+    // Max size of chunk in bytes, same as on master and clients
+    pub chunk_size: u64,
+    // Seconds between scrubber passes over all stored chunks
+    pub scrub_interval: u64,
+    // Bytes per second read by scrubber, so it does not compete with clients
+    pub scrub_bandwidth: u64,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -0,0 +1,83 @@
+use std::{collections::HashMap, sync::Mutex, time::SystemTime};
+
+// Lease granted by master, chunk server is primary replica of chunk until expiration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub expiration: SystemTime,
+    pub secondaries: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Leases {
+    leases: Mutex<HashMap<u64, Lease>>,
+}
+
+impl Leases {
+    pub fn new() -> Self {
+        Leases::default()
+    }
+
+    pub fn grant(&self, chunk_handle: u64, expiration: SystemTime, secondaries: Vec<String>) {
+        self.leases.lock().unwrap().insert(
+            chunk_handle,
+            Lease {
+                expiration,
+                secondaries,
+            },
+        );
+    }
+
+    // Lease that already expired is not extended, master could grant it to other replica
+    pub fn extend(&self, chunk_handle: u64, expiration: SystemTime) {
+        if let Some(lease) = self.leases.lock().unwrap().get_mut(&chunk_handle) {
+            if lease.expiration > SystemTime::now() {
+                lease.expiration = expiration;
+            }
+        }
+    }
+
+    // Returns lease only if it has not expired yet
+    pub fn get(&self, chunk_handle: u64) -> Option<Lease> {
+        self.leases
+            .lock()
+            .unwrap()
+            .get(&chunk_handle)
+            .filter(|lease| lease.expiration > SystemTime::now())
+            .cloned()
+    }
+
+    // Removes expired leases and returns handles of chunks that are still leased
+    pub fn get_leased_chunks(&self) -> Vec<u64> {
+        let now = SystemTime::now();
+        let mut leases = self.leases.lock().unwrap();
+
+        leases.retain(|_, lease| lease.expiration > now);
+
+        leases.keys().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn expired_lease_should_not_be_extended() {
+        let leases = Leases::new();
+        let now = SystemTime::now();
+
+        leases.grant(1, now + Duration::from_secs(60), Vec::new());
+        leases.grant(2, now - Duration::from_secs(1), Vec::new());
+
+        leases.extend(1, now + Duration::from_secs(120));
+        leases.extend(2, now + Duration::from_secs(120));
+
+        assert_eq!(leases.get_leased_chunks(), vec![1]);
+        assert_eq!(
+            leases.leases.lock().unwrap()[&1].expiration,
+            now + Duration::from_secs(120)
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use client::Client;
 use config::get_configuration;
+use leases::Leases;
+use scrubber::Scrubber;
 use server::run;
 use server::ChunkServer;
 use storage::Storage;
@@ -8,8 +10,11 @@ use std::sync::Arc;
 use tokio::net::TcpListener;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
+mod buffer;
 mod client;
 mod config;
+mod leases;
+mod scrubber;
 mod server;
 mod storage;
 
@@ -26,7 +31,20 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let addr = listener.local_addr().unwrap();
 
-    let chunk_server = ChunkServer::new(addr.to_string(), storage.clone());
+    let leases = Arc::new(Leases::new());
+
+    let master_address = format!(
+        "http://{}:{}",
+        configuration.master_host, configuration.master_port
+    );
+
+    let chunk_server = ChunkServer::new(
+        addr.to_string(),
+        master_address,
+        storage.clone(),
+        leases.clone(),
+        configuration.chunk_size,
+    );
 
     let server = run(chunk_server, listener)?;
 
@@ -34,12 +52,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         addr.to_string(),
         configuration.master_host,
         configuration.master_port,
-        60,
+        configuration.heartbeat_interval,
         storage.clone(),
+        leases.clone(),
     );
 
     client.run();
 
+    let scrubber = Scrubber::new(
+        storage.clone(),
+        configuration.scrub_interval,
+        configuration.scrub_bandwidth,
+    );
+
+    scrubber.run();
+
     server.await?;
 
     Ok(())
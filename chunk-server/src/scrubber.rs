@@ -0,0 +1,78 @@
+use std::{io::ErrorKind, sync::Arc, time::Duration};
+
+use tokio::time::{interval, sleep};
+use tracing::{error, info, warn};
+
+use crate::storage::Storage;
+
+// Verifies checksums of chunks that may not be read by clients for a long time.
+// Corrupted chunks are marked by storage and reported to master in next heartbeat.
+pub struct Scrubber {
+    storage: Arc<Storage>,
+    interval: u64,
+    bandwidth: u64,
+}
+
+impl Scrubber {
+    pub fn new(storage: Arc<Storage>, interval: u64, bandwidth: u64) -> Scrubber {
+        Scrubber {
+            storage,
+            interval,
+            bandwidth,
+        }
+    }
+
+    pub fn run(&self) {
+        let storage = self.storage.clone();
+        let bandwidth = self.bandwidth.max(1);
+        let mut interval = interval(Duration::from_secs(self.interval));
+
+        tokio::spawn(async move {
+            loop {
+                interval.tick().await;
+
+                let chunk_handles = storage.get_chunk_handles();
+
+                info!("Scrubbing {} chunks", chunk_handles.len());
+
+                let mut corrupted = 0;
+
+                for chunk_handle in chunk_handles {
+                    let chunk_storage = storage.clone();
+
+                    let result = tokio::task::spawn_blocking(move || {
+                        chunk_storage.verify_chunk(chunk_handle)
+                    })
+                    .await;
+
+                    let size = match result {
+                        Ok(Ok(size)) => size,
+                        Ok(Err(e)) if e.kind() == ErrorKind::InvalidData => {
+                            corrupted += 1;
+                            continue;
+                        }
+                        // Chunk deleted during scrubbing
+                        Ok(Err(e)) if e.kind() == ErrorKind::NotFound => continue,
+                        Ok(Err(e)) => {
+                            error!("Failed to verify chunk: {}, because: {}", chunk_handle, e);
+                            continue;
+                        }
+                        Err(e) => {
+                            error!("Verification of chunk: {} panicked: {}", chunk_handle, e);
+                            continue;
+                        }
+                    };
+
+                    // Pause for time in which chunk should be read at given bandwidth
+                    sleep(Duration::from_secs_f64(size as f64 / bandwidth as f64)).await;
+                }
+
+                if corrupted > 0 {
+                    warn!("Scrubbing found {} corrupted chunks", corrupted);
+                } else {
+                    info!("Scrubbing finished");
+                }
+            }
+        });
+    }
+}
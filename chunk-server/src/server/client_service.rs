@@ -1,43 +1,655 @@
+use std::{cmp::min, io::ErrorKind, pin::Pin};
+
 use common::{
     chunk_server::{
-        client_service_server::ClientService, RetrieveChunkRequest, RetrieveChunkResponse,
-        StoreChunkRequest, StoreChunkResponse,
+        client_service_client::ClientServiceClient, client_service_server::ClientService,
+        CommitChunkRequest, CommitChunkResponse, PushDataRequest, PushDataResponse,
+        RecordAppendRequest, RecordAppendResponse, RetrieveChunkRequest, RetrieveChunkResponse,
+        StoreChunkRequest, StoreChunkResponse, WriteChunkRequest, WriteChunkResponse,
     },
+    master_server::{chunk_service_client::ChunkServiceClient, ReportChunkSizeRequest},
+    max_record_size,
     shared::ChunkData,
+    MESSAGE_SIZE,
 };
-use tonic::{Request, Response, Status};
-use tracing::info;
+use tokio::sync::mpsc::{self, Receiver};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{error, info, warn};
 
 use super::ChunkServer;
 
 #[tonic::async_trait]
 impl ClientService for ChunkServer {
-    #[tracing::instrument(skip(self))]
+    #[tracing::instrument(skip(self, request))]
     async fn store_chunk(
         &self,
-        request: Request<StoreChunkRequest>,
+        request: Request<Streaming<StoreChunkRequest>>,
     ) -> Result<Response<StoreChunkResponse>, Status> {
-        info!("Store chunk request: {:?}", request);
+        let mut stream = request.into_inner();
+
+        let mut chunk_handle: Option<u64> = None;
+        let mut data = Vec::new();
+
+        while let Some(message) = stream.message().await? {
+            let part = message
+                .chunk
+                .ok_or_else(|| Status::invalid_argument("Missing chunk data"))?;
+
+            match &chunk_handle {
+                Some(handle) if *handle != part.chunk_handle => {
+                    return Err(Status::invalid_argument(
+                        "All parts of chunk should have the same chunk handle",
+                    ));
+                }
+                Some(_) => {}
+                None => chunk_handle = Some(part.chunk_handle),
+            }
+
+            data.extend_from_slice(&part.data);
+        }
+
+        let chunk_handle =
+            chunk_handle.ok_or_else(|| Status::invalid_argument("Missing chunk data"))?;
+
+        info!(
+            "Store chunk request for: {}, size: {}",
+            chunk_handle,
+            data.len()
+        );
+
+        self.with_storage(move |storage| storage.store_chunk(chunk_handle, &data))
+            .await
+            .map_err(|e| {
+                error!("Failed to store chunk: {}, because: {}", chunk_handle, e);
+                io_error_to_status(e)
+            })?;
 
         let response = StoreChunkResponse { success: true };
 
         Ok(Response::new(response))
     }
 
+    type RetrieveChunkStream =
+        Pin<Box<dyn Stream<Item = Result<RetrieveChunkResponse, Status>> + Send>>;
+
     #[tracing::instrument(skip(self))]
     async fn retrieve_chunk(
         &self,
         request: Request<RetrieveChunkRequest>,
-    ) -> Result<Response<RetrieveChunkResponse>, Status> {
+    ) -> Result<Response<Self::RetrieveChunkStream>, Status> {
         info!("Retrieve chunk request: {:?}", request);
 
-        let chunk = Some(ChunkData {
-            chunk_handle: "1".to_string(),
-            data: Vec::new(),
-        });
+        let RetrieveChunkRequest {
+            chunk_handle,
+            offset,
+            length,
+        } = request.into_inner();
 
-        let response = RetrieveChunkResponse { chunk };
+        let length = if length == 0 { u64::MAX } else { length };
 
-        Ok(Response::new(response))
+        let data = self
+            .with_storage(move |storage| storage.read_chunk(chunk_handle, offset, length))
+            .await
+            .map_err(|e| {
+                error!("Failed to retrieve chunk: {}, because: {}", chunk_handle, e);
+                io_error_to_status(e)
+            })?;
+
+        // Empty chunk is still sent as single message
+        let parts = (0..data.len().max(1))
+            .step_by(MESSAGE_SIZE)
+            .map(move |start| RetrieveChunkResponse {
+                chunk: Some(ChunkData {
+                    chunk_handle,
+                    data: data[start..min(start + MESSAGE_SIZE, data.len())].to_vec(),
+                }),
+            })
+            .map(Ok);
+
+        let stream: Self::RetrieveChunkStream = Box::pin(tokio_stream::iter(parts));
+
+        Ok(Response::new(stream))
+    }
+
+    #[tracing::instrument(skip(self, request))]
+    async fn push_data(
+        &self,
+        request: Request<Streaming<PushDataRequest>>,
+    ) -> Result<Response<PushDataResponse>, Status> {
+        let mut stream = request.into_inner();
+
+        let first = stream
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Missing data"))?;
+
+        let data_id = first.data_id.clone();
+        let mut chain = first.chain.clone();
+
+        // Next replica starts receiving before this one has whole data
+        let forward = if chain.is_empty() {
+            None
+        } else {
+            let next = chain.remove(0);
+            let (sender, receiver) = mpsc::channel(FORWARD_QUEUE_SIZE);
+            let handle = tokio::spawn(forward_data(next, receiver));
+
+            Some((sender, handle))
+        };
+
+        let mut data = Vec::new();
+        let mut message = Some(first);
+
+        while let Some(part) = message {
+            if part.data_id != data_id {
+                return Err(Status::invalid_argument(
+                    "All parts of data should have the same data id",
+                ));
+            }
+
+            data.extend_from_slice(&part.data);
+
+            if let Some((sender, _)) = &forward {
+                let part = PushDataRequest {
+                    data_id: data_id.clone(),
+                    // Rest of chain is sent only in first message
+                    chain: std::mem::take(&mut chain),
+                    data: part.data,
+                };
+
+                // Error is returned by forwarding task after stream is finished
+                if sender.send(part).await.is_err() {
+                    warn!("Forwarding of data: {} stopped", data_id);
+                }
+            }
+
+            message = stream.message().await?;
+        }
+
+        // Push succeeds only if all replicas down the chain received data
+        if let Some((sender, handle)) = forward {
+            drop(sender);
+
+            handle
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(|e| {
+                    error!("Failed to forward data: {}, because: {}", data_id, e);
+                    e
+                })?;
+        }
+
+        info!("Data: {} received, size: {}", data_id, data.len());
+
+        self.buffer.insert(data_id, data);
+
+        Ok(Response::new(PushDataResponse {}))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn commit_chunk(
+        &self,
+        request: Request<CommitChunkRequest>,
+    ) -> Result<Response<CommitChunkResponse>, Status> {
+        let CommitChunkRequest {
+            chunk_handle,
+            data_id,
+            forwarded,
+            version,
+        } = request.into_inner();
+
+        // Client commits only through primary, secondaries get commit forwarded by it
+        let secondaries = if forwarded {
+            self.check_forwarded(chunk_handle, version)
+                .map_err(Status::failed_precondition)?;
+            Vec::new()
+        } else {
+            self.get_secondaries(chunk_handle)
+                .ok_or_else(|| not_primary(chunk_handle))?
+        };
+
+        // Forwarded commit does not wait for lock, primary holding its own lock while it
+        // waits for this server would deadlock with commit this server forwards to it
+        let _commit_guard = if forwarded {
+            None
+        } else {
+            Some(self.commit_locks.lock(chunk_handle).await)
+        };
+
+        let data = self.buffer.take(&data_id).ok_or_else(|| {
+            Status::not_found(format!("Data: {} was not pushed to chunk server", data_id))
+        })?;
+
+        let size = data.len();
+
+        self.with_storage(move |storage| storage.store_chunk(chunk_handle, &data))
+            .await
+            .map_err(|e| {
+                error!("Failed to store chunk: {}, because: {}", chunk_handle, e);
+                io_error_to_status(e)
+            })?;
+
+        let version = self.storage.get_chunk_version(chunk_handle);
+        let mut failed = Vec::new();
+
+        for secondary in secondaries.iter() {
+            if let Err(e) = forward_commit(secondary, chunk_handle, &data_id, version).await {
+                error!(
+                    "Failed to commit chunk: {} on: {}, because: {}",
+                    chunk_handle, secondary, e
+                );
+                failed.push(secondary.as_str());
+            }
+        }
+
+        // Replicas are inconsistent, client should retry whole mutation
+        if !failed.is_empty() {
+            return Err(Status::aborted(format!(
+                "Chunk: {} not committed on: {}",
+                chunk_handle,
+                failed.join(", ")
+            )));
+        }
+
+        if !forwarded {
+            self.report_chunk_size(chunk_handle).await;
+        }
+
+        info!("Chunk: {} committed, size: {}", chunk_handle, size);
+
+        Ok(Response::new(CommitChunkResponse {}))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn record_append(
+        &self,
+        request: Request<RecordAppendRequest>,
+    ) -> Result<Response<RecordAppendResponse>, Status> {
+        let RecordAppendRequest {
+            chunk_handle,
+            data_id,
+            forwarded,
+            offset,
+            pad,
+            version,
+        } = request.into_inner();
+
+        if forwarded {
+            self.check_forwarded(chunk_handle, version)
+                .map_err(Status::failed_precondition)?;
+
+            // Secondary applies decision made by primary, pushed data is dropped when chunk is padded
+            let data = self.buffer.take(&data_id);
+            let chunk_size = self.chunk_size;
+
+            if pad {
+                self.with_storage(move |storage| storage.pad_chunk(chunk_handle, chunk_size))
+                    .await
+            } else {
+                let data = data.ok_or_else(|| {
+                    Status::not_found(format!("Data: {} was not pushed to chunk server", data_id))
+                })?;
+
+                self.with_storage(move |storage| storage.write_chunk(chunk_handle, offset, &data))
+                    .await
+            }
+            .map_err(|e| {
+                error!(
+                    "Failed to append to chunk: {}, because: {}",
+                    chunk_handle, e
+                );
+                io_error_to_status(e)
+            })?;
+
+            return Ok(Response::new(RecordAppendResponse {
+                offset,
+                chunk_full: pad,
+            }));
+        }
+
+        let secondaries = self
+            .get_secondaries(chunk_handle)
+            .ok_or_else(|| not_primary(chunk_handle))?;
+
+        let _commit_guard = self.commit_locks.lock(chunk_handle).await;
+
+        let data = self.buffer.take(&data_id).ok_or_else(|| {
+            Status::not_found(format!("Data: {} was not pushed to chunk server", data_id))
+        })?;
+
+        if data.len() as u64 > max_record_size(self.chunk_size) {
+            return Err(Status::invalid_argument(format!(
+                "Record size: {} exceeds limit: {}",
+                data.len(),
+                max_record_size(self.chunk_size)
+            )));
+        }
+
+        // Primary picks offset, so concurrent appends never overlap
+        let offset = self.storage.get_chunk_size(chunk_handle).map_err(|e| {
+            error!("Failed to read chunk: {}, because: {}", chunk_handle, e);
+            io_error_to_status(e)
+        })?;
+
+        let pad = offset + data.len() as u64 > self.chunk_size;
+        let chunk_size = self.chunk_size;
+
+        if pad {
+            self.with_storage(move |storage| storage.pad_chunk(chunk_handle, chunk_size))
+                .await
+        } else {
+            self.with_storage(move |storage| storage.write_chunk(chunk_handle, offset, &data))
+                .await
+        }
+        .map_err(|e| {
+            error!(
+                "Failed to append to chunk: {}, because: {}",
+                chunk_handle, e
+            );
+            io_error_to_status(e)
+        })?;
+
+        let version = self.storage.get_chunk_version(chunk_handle);
+        let mut failed = Vec::new();
+
+        for secondary in secondaries.iter() {
+            let forwarded = forward_append(secondary, chunk_handle, &data_id, offset, pad, version);
+
+            if let Err(e) = forwarded.await {
+                error!(
+                    "Failed to append to chunk: {} on: {}, because: {}",
+                    chunk_handle, secondary, e
+                );
+                failed.push(secondary.as_str());
+            }
+        }
+
+        // Record may be already written on some replicas, client retries and record can be duplicated
+        if !failed.is_empty() {
+            return Err(Status::aborted(format!(
+                "Append to chunk: {} failed on: {}",
+                chunk_handle,
+                failed.join(", ")
+            )));
+        }
+
+        self.report_chunk_size(chunk_handle).await;
+
+        info!(
+            "Record appended to chunk: {}, offset: {}, chunk full: {}",
+            chunk_handle, offset, pad
+        );
+
+        Ok(Response::new(RecordAppendResponse {
+            offset,
+            chunk_full: pad,
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn write_chunk(
+        &self,
+        request: Request<WriteChunkRequest>,
+    ) -> Result<Response<WriteChunkResponse>, Status> {
+        let WriteChunkRequest {
+            chunk_handle,
+            data_id,
+            forwarded,
+            offset,
+            version,
+        } = request.into_inner();
+
+        // Client writes only through primary, secondaries get write forwarded by it
+        let secondaries = if forwarded {
+            self.check_forwarded(chunk_handle, version)
+                .map_err(Status::failed_precondition)?;
+            Vec::new()
+        } else {
+            self.get_secondaries(chunk_handle)
+                .ok_or_else(|| not_primary(chunk_handle))?
+        };
+
+        // Primary holds lock while write is forwarded, so secondaries apply writes in its order.
+        // Secondaries don't take it, forwarded writes are already serialized by primary
+        let _commit_guard = if forwarded {
+            None
+        } else {
+            Some(self.commit_locks.lock(chunk_handle).await)
+        };
+
+        let data = self.buffer.take(&data_id).ok_or_else(|| {
+            Status::not_found(format!("Data: {} was not pushed to chunk server", data_id))
+        })?;
+
+        // Offset comes from client, so end is checked for overflow too
+        let end = offset.checked_add(data.len() as u64);
+
+        if end.is_none_or(|end| end > self.chunk_size) {
+            return Err(Status::invalid_argument(format!(
+                "Write at offset: {} of size: {} exceeds chunk size: {}",
+                offset,
+                data.len(),
+                self.chunk_size
+            )));
+        }
+
+        let size = data.len();
+
+        self.with_storage(move |storage| storage.write_chunk(chunk_handle, offset, &data))
+            .await
+            .map_err(|e| {
+                error!("Failed to write chunk: {}, because: {}", chunk_handle, e);
+                io_error_to_status(e)
+            })?;
+
+        let version = self.storage.get_chunk_version(chunk_handle);
+        let mut failed = Vec::new();
+
+        for secondary in secondaries.iter() {
+            if let Err(e) = forward_write(secondary, chunk_handle, &data_id, offset, version).await
+            {
+                error!(
+                    "Failed to write chunk: {} on: {}, because: {}",
+                    chunk_handle, secondary, e
+                );
+                failed.push(secondary.as_str());
+            }
+        }
+
+        // Replicas are inconsistent, client should retry write
+        if !failed.is_empty() {
+            return Err(Status::aborted(format!(
+                "Write to chunk: {} failed on: {}",
+                chunk_handle,
+                failed.join(", ")
+            )));
+        }
+
+        if !forwarded {
+            self.report_chunk_size(chunk_handle).await;
+        }
+
+        info!(
+            "Chunk: {} written at offset: {}, size: {}",
+            chunk_handle, offset, size
+        );
+
+        Ok(Response::new(WriteChunkResponse {}))
+    }
+}
+
+impl ChunkServer {
+    // Mutations are accepted from clients only by primary, which forwards them to secondaries
+    fn get_secondaries(&self, chunk_handle: u64) -> Option<Vec<String>> {
+        self.leases.get(chunk_handle).map(|lease| lease.secondaries)
+    }
+
+    // Sender of forwarded mutation is not authenticated, chunk servers and clients are
+    // expected to run in trusted network. Primary of chunk never gets forwarded mutation
+    // and secondary applies only mutation from primary with the same version of chunk,
+    // so mutation forwarded by old primary or sent by client to single replica is rejected.
+    fn check_forwarded(&self, chunk_handle: u64, version: u64) -> Result<(), String> {
+        if self.leases.get(chunk_handle).is_some() {
+            return Err(format!(
+                "Chunk server is primary of chunk: {}, forwarded mutation rejected",
+                chunk_handle
+            ));
+        }
+
+        let current = self.storage.get_chunk_version(chunk_handle);
+
+        if version != current {
+            return Err(format!(
+                "Forwarded mutation of chunk: {} has version: {}, current: {}",
+                chunk_handle, version, current
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Master learns size of mutated chunk before client is told that mutation succeeded,
+    // so stat after mutation sees it. Failed report is only logged, next heartbeat
+    // carries the size too, until then stat may show older size.
+    async fn report_chunk_size(&self, chunk_handle: u64) {
+        let size = match self.storage.get_chunk_size(chunk_handle) {
+            Ok(size) => size,
+            Err(e) => {
+                warn!(
+                    "Failed to read size of chunk: {}, because: {}",
+                    chunk_handle, e
+                );
+                return;
+            }
+        };
+
+        if let Err(e) = report_chunk_size(&self.master_address, chunk_handle, size).await {
+            warn!(
+                "Failed to report size of chunk: {} to master, because: {}",
+                chunk_handle, e
+            );
+        }
+    }
+}
+
+// Number of messages waiting to be forwarded to next replica
+const FORWARD_QUEUE_SIZE: usize = 16;
+
+// Sends data received from previous replica to next one in chain
+async fn forward_data(address: String, receiver: Receiver<PushDataRequest>) -> Result<(), Status> {
+    let mut client = ClientServiceClient::connect(format!("http://{}", address))
+        .await
+        .map_err(|e| Status::unavailable(format!("Failed to connect to: {}, {}", address, e)))?;
+
+    client.push_data(ReceiverStream::new(receiver)).await?;
+
+    Ok(())
+}
+
+async fn forward_commit(
+    address: &str,
+    chunk_handle: u64,
+    data_id: &str,
+    version: u64,
+) -> Result<(), Status> {
+    let mut client = ClientServiceClient::connect(format!("http://{}", address))
+        .await
+        .map_err(|e| Status::unavailable(format!("Failed to connect to: {}, {}", address, e)))?;
+
+    let request = Request::new(CommitChunkRequest {
+        chunk_handle,
+        data_id: data_id.to_string(),
+        forwarded: true,
+        version,
+    });
+
+    client.commit_chunk(request).await?;
+
+    Ok(())
+}
+
+async fn forward_append(
+    address: &str,
+    chunk_handle: u64,
+    data_id: &str,
+    offset: u64,
+    pad: bool,
+    version: u64,
+) -> Result<(), Status> {
+    let mut client = ClientServiceClient::connect(format!("http://{}", address))
+        .await
+        .map_err(|e| Status::unavailable(format!("Failed to connect to: {}, {}", address, e)))?;
+
+    let request = Request::new(RecordAppendRequest {
+        chunk_handle,
+        data_id: data_id.to_string(),
+        forwarded: true,
+        offset,
+        pad,
+        version,
+    });
+
+    client.record_append(request).await?;
+
+    Ok(())
+}
+
+async fn forward_write(
+    address: &str,
+    chunk_handle: u64,
+    data_id: &str,
+    offset: u64,
+    version: u64,
+) -> Result<(), Status> {
+    let mut client = ClientServiceClient::connect(format!("http://{}", address))
+        .await
+        .map_err(|e| Status::unavailable(format!("Failed to connect to: {}, {}", address, e)))?;
+
+    let request = Request::new(WriteChunkRequest {
+        chunk_handle,
+        data_id: data_id.to_string(),
+        forwarded: true,
+        offset,
+        version,
+    });
+
+    client.write_chunk(request).await?;
+
+    Ok(())
+}
+
+async fn report_chunk_size(
+    master_address: &str,
+    chunk_handle: u64,
+    size: u64,
+) -> Result<(), Status> {
+    let mut client = ChunkServiceClient::connect(master_address.to_string())
+        .await
+        .map_err(|e| {
+            Status::unavailable(format!("Failed to connect to: {}, {}", master_address, e))
+        })?;
+
+    let request = Request::new(ReportChunkSizeRequest { chunk_handle, size });
+
+    client.report_chunk_size(request).await?;
+
+    Ok(())
+}
+
+fn not_primary(chunk_handle: u64) -> Status {
+    Status::failed_precondition(format!(
+        "Chunk server does not hold lease for chunk: {}",
+        chunk_handle
+    ))
+}
+
+fn io_error_to_status(error: std::io::Error) -> Status {
+    match error.kind() {
+        ErrorKind::NotFound => Status::not_found(error.to_string()),
+        ErrorKind::InvalidInput => Status::invalid_argument(error.to_string()),
+        // Checksum mismatch, client should read other replica
+        ErrorKind::InvalidData => Status::data_loss(error.to_string()),
+        _ => Status::internal(error.to_string()),
     }
 }
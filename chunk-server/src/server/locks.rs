@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{Mutex as AsyncMutex, OwnedMutexGuard};
+
+// Locks mutations of single chunk, mutations of different chunks run in parallel.
+// Lock exists only while it is held or awaited, so map does not grow with stored chunks.
+#[derive(Debug, Default)]
+pub struct ChunkLocks {
+    locks: Mutex<HashMap<u64, Arc<AsyncMutex<()>>>>,
+}
+
+// Releases lock when dropped
+#[must_use]
+#[derive(Debug)]
+pub struct ChunkGuard<'a> {
+    locks: &'a ChunkLocks,
+    chunk_handle: u64,
+    lock: Arc<AsyncMutex<()>>,
+    guard: Option<OwnedMutexGuard<()>>,
+}
+
+impl ChunkLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn lock(&self, chunk_handle: u64) -> ChunkGuard<'_> {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(chunk_handle)
+            .or_default()
+            .clone();
+
+        let guard = lock.clone().lock_owned().await;
+
+        ChunkGuard {
+            locks: self,
+            chunk_handle,
+            lock,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl Drop for ChunkGuard<'_> {
+    fn drop(&mut self) {
+        self.guard.take();
+
+        let mut locks = self.locks.locks.lock().unwrap();
+
+        // Lock is referenced only by map and this guard, nobody waits for it
+        if Arc::strong_count(&self.lock) == 2 {
+            locks.remove(&self.chunk_handle);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lock_should_block_only_the_same_chunk() {
+        let locks = ChunkLocks::new();
+
+        let guard = locks.lock(1).await;
+
+        // Other chunk is not blocked
+        drop(locks.lock(2).await);
+
+        let blocked = tokio::time::timeout(Duration::from_millis(50), locks.lock(1)).await;
+        assert!(blocked.is_err());
+
+        drop(guard);
+        drop(locks.lock(1).await);
+
+        // Released locks are removed
+        assert!(locks.locks.lock().unwrap().is_empty());
+    }
+}
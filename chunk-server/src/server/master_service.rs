@@ -1,11 +1,14 @@
 use common::{
     chunk_server::{
-        master_service_server::MasterService, AcquireChunksRequest, GrantLeaseRequest,
-        GrantLeaseResponse,
+        client_service_client::ClientServiceClient, master_service_server::MasterService,
+        AcquireChunksRequest, ChunkData, GrantLeaseRequest, GrantLeaseResponse,
+        RetrieveChunkRequest, UpdateChunkVersionRequest,
     },
     shared::EmptyReply,
+    time::from_unix_millis,
 };
 use tonic::{Request, Response, Status};
+use tracing::{error, info};
 
 use super::ChunkServer;
 
@@ -16,7 +19,41 @@ impl MasterService for ChunkServer {
         &self,
         request: Request<GrantLeaseRequest>,
     ) -> Result<Response<GrantLeaseResponse>, Status> {
-        todo!()
+        let GrantLeaseRequest {
+            chunk_handle,
+            expiration,
+            secondaries,
+        } = request.into_inner();
+
+        info!("Lease for chunk: {} granted", chunk_handle);
+
+        self.leases
+            .grant(chunk_handle, from_unix_millis(expiration), secondaries);
+
+        Ok(Response::new(GrantLeaseResponse {}))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn update_chunk_version(
+        &self,
+        request: Request<UpdateChunkVersionRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let UpdateChunkVersionRequest {
+            chunk_handle,
+            version,
+        } = request.into_inner();
+
+        self.with_storage(move |storage| storage.set_chunk_version(chunk_handle, version))
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to update version of chunk: {}, because: {}",
+                    chunk_handle, e
+                );
+                Status::internal(e.to_string())
+            })?;
+
+        Ok(Response::new(EmptyReply {}))
     }
 
     #[tracing::instrument(skip(self))]
@@ -24,6 +61,61 @@ impl MasterService for ChunkServer {
         &self,
         request: Request<AcquireChunksRequest>,
     ) -> Result<Response<EmptyReply>, Status> {
-        todo!()
+        let chunks_to_acquire = request.into_inner().chunks_to_acquire;
+
+        for ChunkData {
+            chunk_handle,
+            address,
+            version,
+        } in chunks_to_acquire
+        {
+            info!("Acquiring chunk: {} from: {}", chunk_handle, address);
+
+            let data = fetch_chunk(&address, chunk_handle).await.map_err(|e| {
+                error!(
+                    "Failed to fetch chunk: {} from: {}, because: {}",
+                    chunk_handle, address, e
+                );
+                e
+            })?;
+
+            // Copy without version is stale, so crash between these leaves nothing to serve
+            self.with_storage(move |storage| {
+                storage
+                    .store_chunk(chunk_handle, &data)
+                    .and_then(|()| storage.set_chunk_version(chunk_handle, version))
+            })
+            .await
+            .map_err(|e| {
+                error!("Failed to store chunk: {}, because: {}", chunk_handle, e);
+                Status::internal(e.to_string())
+            })?;
+        }
+
+        Ok(Response::new(EmptyReply {}))
     }
 }
+
+// Reads whole chunk from other chunk server
+async fn fetch_chunk(address: &str, chunk_handle: u64) -> Result<Vec<u8>, Status> {
+    let mut client = ClientServiceClient::connect(format!("http://{}", address))
+        .await
+        .map_err(|e| Status::unavailable(format!("Failed to connect to: {}, {}", address, e)))?;
+
+    let request = Request::new(RetrieveChunkRequest {
+        chunk_handle,
+        ..Default::default()
+    });
+
+    let mut stream = client.retrieve_chunk(request).await?.into_inner();
+
+    let mut data = Vec::new();
+
+    while let Some(response) = stream.message().await? {
+        if let Some(chunk) = response.chunk {
+            data.extend_from_slice(&chunk.data);
+        }
+    }
+
+    Ok(data)
+}
@@ -10,21 +10,61 @@ use uuid::Uuid;
 use common::chunk_server::client_service_server::ClientServiceServer;
 use common::chunk_server::master_service_server::MasterServiceServer;
 
+use crate::buffer::DataBuffer;
+use crate::leases::Leases;
 use crate::storage::Storage;
 
+use locks::ChunkLocks;
+
 mod client_service;
+mod locks;
 mod master_service;
 
 #[derive(Debug, Default)]
 pub struct ChunkServer {
     address: String,
+    // Primary reports sizes of mutated chunks to master
+    master_address: String,
     storage: Arc<Storage>,
+    leases: Arc<Leases>,
+    buffer: DataBuffer,
+    chunk_size: u64,
+    // Primary commits one mutation of chunk at a time, so all replicas apply them
+    // in the same order. Mutations of different chunks are not ordered.
+    commit_locks: ChunkLocks,
 }
 
 impl ChunkServer {
     #[tracing::instrument]
-    pub fn new(address: String, storage: Arc<Storage>) -> Self {
-        ChunkServer { address, storage }
+    pub fn new(
+        address: String,
+        master_address: String,
+        storage: Arc<Storage>,
+        leases: Arc<Leases>,
+        chunk_size: u64,
+    ) -> Self {
+        ChunkServer {
+            address,
+            master_address,
+            storage,
+            leases,
+            buffer: DataBuffer::new(),
+            chunk_size,
+            commit_locks: ChunkLocks::new(),
+        }
+    }
+
+    // Storage blocks on file IO and fsync, so it runs outside of threads serving requests
+    async fn with_storage<T, F>(&self, f: F) -> Result<T, std::io::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Storage) -> Result<T, std::io::Error> + Send + 'static,
+    {
+        let storage = self.storage.clone();
+
+        tokio::task::spawn_blocking(move || f(&storage))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)))
     }
 }
 
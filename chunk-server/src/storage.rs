@@ -1,18 +1,41 @@
 use std::{
-    env, fs,
+    collections::{HashMap, HashSet},
+    env,
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Write},
+    os::unix::fs::FileExt,
     path::{Path, PathBuf},
     process::Command,
-    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
 };
 
 use tracing::{error, info};
 
+// Suffix of files that are being written and were not yet renamed to final chunk file
+const TMP_SUFFIX: &str = ".tmp";
+// Suffix of files with checksums of chunk blocks, one little endian CRC32C per block
+const CHECKSUM_SUFFIX: &str = ".crc";
+// Suffix of files with version of chunk, chunk without one has version 0
+const VERSION_SUFFIX: &str = ".ver";
+// Chunk is checksummed in blocks, so small write or read does not touch whole chunk
+const BLOCK_SIZE: u64 = 64 * 1024;
+const CHECKSUM_SIZE: u64 = 4;
+
 #[derive(Debug, Default)]
 pub struct Storage {
-    used: u64,
-    available: u64,
+    // used and available are in bytes
+    used: Mutex<u64>,
+    available: Mutex<u64>,
     data_path: PathBuf,
-    chunk_handles: Vec<String>,
+    chunk_handles: Mutex<HashSet<u64>>,
+    // Versions set by master when it granted lease, persisted in version files
+    versions: Mutex<HashMap<u64, u64>>,
+    // Chunks that failed checksum verification, reported to master until they are deleted
+    corrupted: Mutex<HashSet<u64>>,
+    // Readers should not see data and checksums of chunk from different writes.
+    // Lock of chunk is created on first access and dropped with chunk.
+    files_locks: Mutex<HashMap<u64, Arc<RwLock<()>>>>,
 }
 
 impl Storage {
@@ -37,33 +60,556 @@ impl Storage {
             ),
         }
 
+        Storage::open(full_path)
+    }
+
+    // Opens storage in already existing directory
+    pub fn open(data_path: PathBuf) -> Self {
+        finish_interrupted_stores(&data_path);
+
         // Get disc_usage
         let (used, available) = get_disc_usage();
 
         // Get stored chunks
-        let chunk_handles = get_stored_chunk_handles(full_path.to_str().unwrap());
+        let chunk_handles = get_stored_chunk_handles(data_path.to_str().unwrap());
+
+        let versions = chunk_handles
+            .iter()
+            .map(|chunk_handle| {
+                let version = read_version(&data_path, *chunk_handle).unwrap_or_else(|e| {
+                    error!(
+                        "Failed to read version of chunk: {}, because: {}",
+                        chunk_handle, e
+                    );
+                    0
+                });
+
+                (*chunk_handle, version)
+            })
+            .collect();
 
         Storage {
-            used,
-            available,
-            data_path: PathBuf::from_str(data_path).expect("data_path should be created"),
-            chunk_handles,
+            used: Mutex::new(used),
+            available: Mutex::new(available),
+            data_path,
+            chunk_handles: Mutex::new(chunk_handles.into_iter().collect()),
+            versions: Mutex::new(versions),
+            corrupted: Mutex::new(HashSet::new()),
+            files_locks: Mutex::new(HashMap::new()),
         }
     }
 
     pub fn get_used_storage(&self) -> u64 {
-        self.used
+        *self.used.lock().unwrap()
     }
 
     pub fn get_available_storage(&self) -> u64 {
-        self.available
+        *self.available.lock().unwrap()
+    }
+
+    // Corrupted chunks are not reported as stored
+    pub fn get_chunk_handles(&self) -> Vec<u64> {
+        let corrupted = self.corrupted.lock().unwrap();
+
+        self.chunk_handles
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|chunk_handle| !corrupted.contains(*chunk_handle))
+            .copied()
+            .collect()
+    }
+
+    pub fn get_corrupted_chunks(&self) -> Vec<u64> {
+        self.corrupted.lock().unwrap().iter().copied().collect()
+    }
+
+    pub fn get_chunk_version(&self, chunk_handle: u64) -> u64 {
+        self.versions
+            .lock()
+            .unwrap()
+            .get(&chunk_handle)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Version is never lowered, so updates from concurrent lease grants can arrive in any order.
+    // Chunk that was not written yet is created empty, so it is reported with its version.
+    pub fn set_chunk_version(&self, chunk_handle: u64, version: u64) -> Result<(), Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        let mut versions = self.versions.lock().unwrap();
+
+        if versions
+            .get(&chunk_handle)
+            .is_some_and(|current| *current >= version)
+        {
+            return Ok(());
+        }
+
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(chunk_path)?;
+
+        let version_path = self.version_path(chunk_handle);
+        let tmp_path = self
+            .data_path
+            .join(format!("{}{}{}", chunk_handle, VERSION_SUFFIX, TMP_SUFFIX));
+
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(version.to_string().as_bytes())?;
+        file.sync_all()?;
+
+        fs::rename(&tmp_path, &version_path)?;
+
+        versions.insert(chunk_handle, version);
+
+        self.chunk_handles.lock().unwrap().insert(chunk_handle);
+
+        info!("Chunk: {} version set to: {}", chunk_handle, version);
+
+        Ok(())
+    }
+
+    pub fn store_chunk(&self, chunk_handle: u64, data: &[u8]) -> Result<(), Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+        let checksum_path = self.checksum_path(chunk_handle);
+        let tmp_path = self
+            .data_path
+            .join(format!("{}{}", chunk_handle, TMP_SUFFIX));
+        let tmp_checksum_path = self
+            .data_path
+            .join(format!("{}{}{}", chunk_handle, CHECKSUM_SUFFIX, TMP_SUFFIX));
+
+        // Write to temporary file first, so crash during write never leaves partial chunk
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+
+        let mut checksum_file = File::create(&tmp_checksum_path)?;
+        checksum_file.write_all(&compute_checksums(data))?;
+        checksum_file.sync_all()?;
+
+        let lock = self.files_lock(chunk_handle);
+        let _guard = lock.write().unwrap();
+
+        let previous_size = fs::metadata(&chunk_path).map(|m| m.len()).ok();
+
+        // Data is renamed first, checksums left in temporary file by crash between renames
+        // belong to new data and are moved in place when storage is opened
+        fs::rename(&tmp_path, &chunk_path)?;
+        fs::rename(&tmp_checksum_path, &checksum_path)?;
+
+        // New copy replaces corrupted one
+        self.corrupted.lock().unwrap().remove(&chunk_handle);
+
+        let previous_size = previous_size.unwrap_or(0);
+        let written = data.len() as u64;
+
+        self.chunk_handles.lock().unwrap().insert(chunk_handle);
+
+        self.update_usage(written, previous_size);
+
+        info!("Chunk: {} stored, size: {}", chunk_handle, written);
+
+        Ok(())
     }
 
-    pub fn get_chunk_handles(&self) -> Vec<String> {
-        self.chunk_handles.clone()
+    // Returns 0 for chunk that was not written yet
+    pub fn get_chunk_size(&self, chunk_handle: u64) -> Result<u64, Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        match fs::metadata(chunk_path) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e),
+        }
+    }
+
+    // Time of last write to chunk file
+    pub fn get_chunk_modified(&self, chunk_handle: u64) -> Result<SystemTime, Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        fs::metadata(chunk_path)?.modified()
+    }
+
+    // Writes data in place, gap between end of chunk and offset is filled with zeros.
+    // Unlike store_chunk, failed write can leave chunk partially written.
+    pub fn write_chunk(&self, chunk_handle: u64, offset: u64, data: &[u8]) -> Result<(), Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        let lock = self.files_lock(chunk_handle);
+        let _guard = lock.write().unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(chunk_path)?;
+
+        let previous_size = file.metadata()?.len();
+        let end = offset + data.len() as u64;
+
+        // Blocks partially overwritten keep some old data, it should not be corrupted
+        // before checksum is recomputed for them
+        let first = offset.min(previous_size);
+        self.verify_block(chunk_handle, &file, previous_size, first / BLOCK_SIZE)?;
+        if end > 0 {
+            self.verify_block(chunk_handle, &file, previous_size, (end - 1) / BLOCK_SIZE)?;
+        }
+
+        file.write_all_at(data, offset)?;
+        file.sync_data()?;
+
+        let size = previous_size.max(end);
+
+        self.update_checksums(chunk_handle, &file, first, size)?;
+
+        self.chunk_handles.lock().unwrap().insert(chunk_handle);
+
+        self.update_usage(size - previous_size, 0);
+
+        Ok(())
+    }
+
+    // Extends chunk with zeros to given size, larger chunk is left unchanged
+    pub fn pad_chunk(&self, chunk_handle: u64, size: u64) -> Result<(), Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        let lock = self.files_lock(chunk_handle);
+        let _guard = lock.write().unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(chunk_path)?;
+
+        let previous_size = file.metadata()?.len();
+
+        if previous_size < size {
+            self.verify_block(
+                chunk_handle,
+                &file,
+                previous_size,
+                previous_size / BLOCK_SIZE,
+            )?;
+
+            file.set_len(size)?;
+            file.sync_data()?;
+
+            self.update_checksums(chunk_handle, &file, previous_size, size)?;
+
+            self.update_usage(size - previous_size, 0);
+        }
+
+        self.chunk_handles.lock().unwrap().insert(chunk_handle);
+
+        Ok(())
+    }
+
+    pub fn retrieve_chunk(&self, chunk_handle: u64) -> Result<Vec<u8>, Error> {
+        self.read_chunk(chunk_handle, 0, u64::MAX)
+    }
+
+    // Reads at most length bytes from offset, less is returned at end of chunk.
+    // Only blocks covering the range are read and verified.
+    pub fn read_chunk(
+        &self,
+        chunk_handle: u64,
+        offset: u64,
+        length: u64,
+    ) -> Result<Vec<u8>, Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        if !self.chunk_handles.lock().unwrap().contains(&chunk_handle) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("Chunk: {} not found", chunk_handle),
+            ));
+        }
+
+        let lock = self.files_lock(chunk_handle);
+        let _guard = lock.read().unwrap();
+
+        let file = File::open(chunk_path)?;
+        let size = file.metadata()?.len();
+        let checksums = self.read_checksums(chunk_handle)?;
+
+        // Missing or extra checksums are treated as corruption too
+        if checksums.len() as u64 != block_count(size) {
+            return Err(self.corrupted(chunk_handle, checksums.len() as u64));
+        }
+
+        let end = offset.saturating_add(length).min(size);
+
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+
+        let first_block = offset / BLOCK_SIZE;
+        let start = first_block * BLOCK_SIZE;
+        let blocks_end = (block_count(end) * BLOCK_SIZE).min(size);
+
+        let mut data = vec![0; (blocks_end - start) as usize];
+        file.read_exact_at(&mut data, start)?;
+
+        for (i, block) in data.chunks(BLOCK_SIZE as usize).enumerate() {
+            let block_index = first_block + i as u64;
+
+            if crc32c::crc32c(block) != checksums[block_index as usize] {
+                return Err(self.corrupted(chunk_handle, block_index));
+            }
+        }
+
+        Ok(data[(offset - start) as usize..(end - start) as usize].to_vec())
+    }
+
+    // Reads chunk only to verify its checksums, returns size of verified chunk
+    pub fn verify_chunk(&self, chunk_handle: u64) -> Result<u64, Error> {
+        self.retrieve_chunk(chunk_handle)
+            .map(|data| data.len() as u64)
+    }
+
+    // Deleting chunk that is not stored is not an error, master can repeat to_delete
+    pub fn delete_chunk(&self, chunk_handle: u64) -> Result<(), Error> {
+        let chunk_path = self.chunk_path(chunk_handle);
+
+        let size = match fs::metadata(&chunk_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+
+        let lock = self.files_lock(chunk_handle);
+        let guard = lock.write().unwrap();
+
+        for path in [
+            &chunk_path,
+            &self.checksum_path(chunk_handle),
+            &self.version_path(chunk_handle),
+        ] {
+            match fs::remove_file(path) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        self.chunk_handles.lock().unwrap().remove(&chunk_handle);
+        self.corrupted.lock().unwrap().remove(&chunk_handle);
+        self.versions.lock().unwrap().remove(&chunk_handle);
+
+        drop(guard);
+        self.forget_files_lock(chunk_handle, &lock);
+
+        self.update_usage(0, size);
+
+        info!("Chunk: {} deleted", chunk_handle);
+
+        Ok(())
+    }
+
+    fn files_lock(&self, chunk_handle: u64) -> Arc<RwLock<()>> {
+        self.files_locks
+            .lock()
+            .unwrap()
+            .entry(chunk_handle)
+            .or_default()
+            .clone()
+    }
+
+    // Lock is dropped only when nobody else holds or waits for it, locks are cloned
+    // only under map lock, so nobody can get it after it is removed
+    fn forget_files_lock(&self, chunk_handle: u64, lock: &Arc<RwLock<()>>) {
+        let mut locks = self.files_locks.lock().unwrap();
+
+        if Arc::strong_count(lock) == 2 {
+            locks.remove(&chunk_handle);
+        }
+    }
+
+    // Missing checksum file means that chunk has no blocks
+    fn read_checksums(&self, chunk_handle: u64) -> Result<Vec<u32>, Error> {
+        let bytes = match fs::read(self.checksum_path(chunk_handle)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(bytes
+            .chunks_exact(CHECKSUM_SIZE as usize)
+            .map(|checksum| u32::from_le_bytes(checksum.try_into().unwrap()))
+            .collect())
+    }
+
+    // Block past end of chunk has nothing to verify
+    fn verify_block(
+        &self,
+        chunk_handle: u64,
+        file: &File,
+        size: u64,
+        block: u64,
+    ) -> Result<(), Error> {
+        let start = block * BLOCK_SIZE;
+
+        if start >= size {
+            return Ok(());
+        }
+
+        let mut data = vec![0; (size - start).min(BLOCK_SIZE) as usize];
+        file.read_exact_at(&mut data, start)?;
+
+        let checksum = self
+            .read_checksums(chunk_handle)?
+            .get(block as usize)
+            .copied();
+
+        if checksum != Some(crc32c::crc32c(&data)) {
+            return Err(self.corrupted(chunk_handle, block));
+        }
+
+        Ok(())
+    }
+
+    // Recomputes checksums of blocks between from and size of chunk
+    fn update_checksums(
+        &self,
+        chunk_handle: u64,
+        file: &File,
+        from: u64,
+        size: u64,
+    ) -> Result<(), Error> {
+        let start = from / BLOCK_SIZE * BLOCK_SIZE;
+
+        let mut data = vec![0; (size - start) as usize];
+        file.read_exact_at(&mut data, start)?;
+
+        let checksum_file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(self.checksum_path(chunk_handle))?;
+
+        checksum_file.write_all_at(
+            &compute_checksums(&data),
+            start / BLOCK_SIZE * CHECKSUM_SIZE,
+        )?;
+        checksum_file.set_len(block_count(size) * CHECKSUM_SIZE)?;
+        checksum_file.sync_data()?;
+
+        Ok(())
+    }
+
+    // Marks chunk as corrupted, master is told about it in next heartbeat
+    fn corrupted(&self, chunk_handle: u64, block: u64) -> Error {
+        error!(
+            "Chunk: {} is corrupted, checksum mismatch in block: {}",
+            chunk_handle, block
+        );
+
+        self.corrupted.lock().unwrap().insert(chunk_handle);
+
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("Chunk: {} is corrupted", chunk_handle),
+        )
+    }
+
+    fn update_usage(&self, added: u64, removed: u64) {
+        let mut used = self.used.lock().unwrap();
+        let mut available = self.available.lock().unwrap();
+
+        *used = used.saturating_add(added).saturating_sub(removed);
+        *available = available.saturating_sub(added).saturating_add(removed);
+    }
+
+    // Handle is used as file name
+    fn chunk_path(&self, chunk_handle: u64) -> PathBuf {
+        self.data_path.join(chunk_handle.to_string())
+    }
+
+    fn checksum_path(&self, chunk_handle: u64) -> PathBuf {
+        self.data_path
+            .join(format!("{}{}", chunk_handle, CHECKSUM_SUFFIX))
+    }
+
+    fn version_path(&self, chunk_handle: u64) -> PathBuf {
+        self.data_path
+            .join(format!("{}{}", chunk_handle, VERSION_SUFFIX))
+    }
+}
+
+// Missing version file means that master never granted lease for chunk
+fn read_version(data_path: &Path, chunk_handle: u64) -> Result<u64, Error> {
+    let path = data_path.join(format!("{}{}", chunk_handle, VERSION_SUFFIX));
+
+    match fs::read_to_string(path) {
+        Ok(version) => version
+            .trim()
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+// Store interrupted before data was renamed leaves old chunk in place, its temporary files
+// are removed. Store interrupted between renames has new data, so its checksums are moved in.
+fn finish_interrupted_stores(data_path: &Path) {
+    let entries = match fs::read_dir(data_path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to list directory: {:?}, because: {}", data_path, e);
+            return;
+        }
+    };
+
+    let checksum_tmp_suffix = format!("{}{}", CHECKSUM_SUFFIX, TMP_SUFFIX);
+
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(chunk_handle) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(&checksum_tmp_suffix))
+            .and_then(|handle| handle.parse::<u64>().ok())
+        else {
+            continue;
+        };
+
+        let tmp_path = data_path.join(format!("{}{}", chunk_handle, TMP_SUFFIX));
+        let checksum_path = data_path.join(format!("{}{}", chunk_handle, CHECKSUM_SUFFIX));
+
+        let result = if tmp_path.exists() {
+            fs::remove_file(&tmp_path).and_then(|()| fs::remove_file(entry.path()))
+        } else {
+            fs::rename(entry.path(), checksum_path)
+        };
+
+        match result {
+            Ok(()) => info!("Interrupted store of chunk: {} finished", chunk_handle),
+            Err(e) => error!(
+                "Failed to finish interrupted store of chunk: {}, because: {}",
+                chunk_handle, e
+            ),
+        }
     }
 }
 
+fn compute_checksums(data: &[u8]) -> Vec<u8> {
+    data.chunks(BLOCK_SIZE as usize)
+        .flat_map(|block| crc32c::crc32c(block).to_le_bytes())
+        .collect()
+}
+
+fn block_count(size: u64) -> u64 {
+    size.div_ceil(BLOCK_SIZE)
+}
+
 fn get_disc_usage() -> (u64, u64) {
     let disc_usage = Command::new("df")
         .arg("-k")
@@ -85,17 +631,20 @@ fn get_disc_usage() -> (u64, u64) {
 
     info!("{:?}", data);
 
-    let used = data.first().unwrap().parse().unwrap();
-    let available = data.get(1).unwrap().parse().unwrap();
+    // df reports values in 1K blocks
+    let used = data.first().unwrap().parse::<u64>().unwrap() * 1024;
+    let available = data.get(1).unwrap().parse::<u64>().unwrap() * 1024;
 
     info!("disc_usage: used:{:?}, available: {:?}", used, available);
 
     (used, available)
 }
 
-fn get_stored_chunk_handles(data_path: &str) -> Vec<String> {
+fn get_stored_chunk_handles(data_path: &str) -> Vec<u64> {
     let files = Command::new("find")
         .arg(data_path)
+        .arg("-maxdepth")
+        .arg("1")
         .arg("-type")
         .arg("f")
         .output()
@@ -105,17 +654,207 @@ fn get_stored_chunk_handles(data_path: &str) -> Vec<String> {
 
     let files = String::from_utf8(files.stdout).expect("Failed to convert output to string");
 
-    let filenames: Vec<String> = files
+    // Leftovers of interrupted writes, checksums and versions are not valid handles
+    let chunk_handles: Vec<u64> = files
         .lines()
         .filter_map(|line| {
             Path::new(line)
                 .file_name()
                 .and_then(|name| name.to_str())
-                .map(|name| name.to_string())
+                .and_then(|name| name.parse().ok())
         })
         .collect();
 
-    info!("stored file names: {:?}", filenames);
+    info!("stored chunk handles: {:?}", chunk_handles);
+
+    chunk_handles
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn test_storage() -> Storage {
+        let path = env::temp_dir().join(format!("chunk-server-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        Storage::open(path)
+    }
+
+    #[test]
+    fn stored_chunk_should_be_retrieved() {
+        let storage = test_storage();
+        let used = storage.get_used_storage();
+
+        storage.store_chunk(42, b"chunk data").unwrap();
+
+        assert_eq!(storage.retrieve_chunk(42).unwrap(), b"chunk data");
+        assert_eq!(storage.get_chunk_handles(), vec![42]);
+        assert_eq!(storage.get_used_storage(), used + 10);
+
+        // Chunks stored before restart should be found again
+        let reopened = Storage::open(storage.data_path.clone());
+        assert_eq!(reopened.retrieve_chunk(42).unwrap(), b"chunk data");
+    }
+
+    #[test]
+    fn chunk_should_be_written_in_place_and_padded() {
+        let storage = test_storage();
+
+        assert_eq!(storage.get_chunk_size(7).unwrap(), 0);
+
+        storage.write_chunk(7, 0, b"first").unwrap();
+        storage.write_chunk(7, 7, b"second").unwrap();
+
+        assert_eq!(storage.retrieve_chunk(7).unwrap(), b"first\0\0second");
+
+        storage.pad_chunk(7, 16).unwrap();
+
+        assert_eq!(storage.get_chunk_size(7).unwrap(), 16);
+        assert_eq!(storage.retrieve_chunk(7).unwrap(), b"first\0\0second\0\0\0");
+    }
+
+    #[test]
+    fn deleted_chunk_should_not_be_retrieved() {
+        let storage = test_storage();
+        let used = storage.get_used_storage();
+
+        storage.store_chunk(42, b"chunk data").unwrap();
+        storage.delete_chunk(42).unwrap();
+
+        assert!(storage.retrieve_chunk(42).is_err());
+        assert!(storage.get_chunk_handles().is_empty());
+        assert_eq!(storage.get_used_storage(), used);
+
+        // Repeated delete is ignored
+        storage.delete_chunk(42).unwrap();
+    }
+
+    #[test]
+    fn chunk_should_be_read_in_ranges() {
+        let storage = test_storage();
+        let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|i| (i % 251) as u8).collect();
+
+        storage.store_chunk(42, &data).unwrap();
+
+        let offset = BLOCK_SIZE as usize - 5;
+
+        assert_eq!(
+            storage.read_chunk(42, offset as u64, 10).unwrap(),
+            data[offset..offset + 10]
+        );
+        assert_eq!(
+            storage.read_chunk(42, 2 * BLOCK_SIZE, u64::MAX).unwrap(),
+            data[2 * BLOCK_SIZE as usize..]
+        );
+        assert!(storage
+            .read_chunk(42, 3 * BLOCK_SIZE, 10)
+            .unwrap()
+            .is_empty());
+
+        // Corrupted block outside of range is not read
+        let file = OpenOptions::new()
+            .write(true)
+            .open(storage.data_path.join("42"))
+            .unwrap();
+        file.write_all_at(b"x", 0).unwrap();
 
-    filenames
+        assert!(storage.read_chunk(42, BLOCK_SIZE, 10).is_ok());
+        assert!(storage.read_chunk(42, 0, 10).is_err());
+    }
+
+    #[test]
+    fn corrupted_chunk_should_be_detected_and_reported() {
+        let storage = test_storage();
+        let data = vec![7; BLOCK_SIZE as usize + 100];
+
+        storage.store_chunk(42, &data).unwrap();
+        storage.write_chunk(42, BLOCK_SIZE - 2, b"data").unwrap();
+        storage.pad_chunk(42, 2 * BLOCK_SIZE + 1).unwrap();
+        assert!(storage.retrieve_chunk(42).is_ok());
+        assert_eq!(storage.verify_chunk(42).unwrap(), 2 * BLOCK_SIZE + 1);
+
+        // Flip one byte in second block on disk
+        let file = OpenOptions::new()
+            .write(true)
+            .open(storage.data_path.join("42"))
+            .unwrap();
+        file.write_all_at(b"x", BLOCK_SIZE + 10).unwrap();
+
+        let error = storage.retrieve_chunk(42).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert_eq!(storage.get_corrupted_chunks(), vec![42]);
+        assert!(storage.get_chunk_handles().is_empty());
+
+        // Partially overwritten block is verified before write
+        let error = storage.write_chunk(42, BLOCK_SIZE, b"x").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+        storage.delete_chunk(42).unwrap();
+        assert!(storage.get_corrupted_chunks().is_empty());
+    }
+
+    #[test]
+    fn chunk_version_should_be_persisted_and_never_lowered() {
+        let storage = test_storage();
+
+        assert_eq!(storage.get_chunk_version(42), 0);
+
+        // Version can be set before chunk is written
+        storage.set_chunk_version(42, 2).unwrap();
+        storage.set_chunk_version(42, 1).unwrap();
+        storage.write_chunk(42, 0, b"data").unwrap();
+
+        assert_eq!(storage.get_chunk_version(42), 2);
+        assert_eq!(storage.get_chunk_handles(), vec![42]);
+
+        let reopened = Storage::open(storage.data_path.clone());
+        assert_eq!(reopened.get_chunk_version(42), 2);
+        assert_eq!(reopened.get_chunk_handles(), vec![42]);
+
+        reopened.delete_chunk(42).unwrap();
+        assert_eq!(reopened.get_chunk_version(42), 0);
+    }
+
+    #[test]
+    fn only_chunk_files_should_be_found_after_restart() {
+        let storage = test_storage();
+
+        storage.store_chunk(42, b"chunk data").unwrap();
+        storage.set_chunk_version(42, 1).unwrap();
+        fs::write(storage.data_path.join("43.tmp"), b"partial").unwrap();
+        fs::write(storage.data_path.join("notes"), b"not a chunk").unwrap();
+
+        let reopened = Storage::open(storage.data_path.clone());
+        assert_eq!(reopened.get_chunk_handles(), vec![42]);
+    }
+
+    #[test]
+    fn interrupted_store_should_be_finished_on_open() {
+        let storage = test_storage();
+        let path = |name: &str| storage.data_path.join(name);
+
+        storage.store_chunk(42, b"old data").unwrap();
+        storage.store_chunk(43, b"old data").unwrap();
+
+        // Crash before data was renamed keeps old chunk
+        fs::write(path("42.tmp"), b"new data").unwrap();
+        fs::write(path("42.crc.tmp"), compute_checksums(b"new data")).unwrap();
+
+        // Crash between renames keeps new data with its checksums
+        fs::write(path("43"), b"new data").unwrap();
+        fs::write(path("43.crc.tmp"), compute_checksums(b"new data")).unwrap();
+
+        let reopened = Storage::open(storage.data_path.clone());
+
+        assert_eq!(reopened.retrieve_chunk(42).unwrap(), b"old data");
+        assert_eq!(reopened.retrieve_chunk(43).unwrap(), b"new data");
+        assert!(reopened.get_corrupted_chunks().is_empty());
+        assert!(!path("42.tmp").exists());
+        assert!(!path("42.crc.tmp").exists());
+        assert!(!path("43.crc.tmp").exists());
+    }
 }
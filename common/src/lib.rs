@@ -1,3 +1,14 @@
+// Max size of chunk data sent in single grpc message, grpc limits messages to 4MB by default
+pub const MESSAGE_SIZE: usize = 1024 * 1024;
+
+// Appended record can take at most quarter of chunk, so padding wastes at most that much space
+pub fn max_record_size(chunk_size: u64) -> u64 {
+    chunk_size / 4
+}
+
+pub mod path;
+pub mod time;
+
 pub mod master_server {
     tonic::include_proto!("dfs.master_server");
 }
@@ -0,0 +1,170 @@
+use std::fmt::{self, Display};
+
+// Same limits as most local file systems
+pub const MAX_NAME_LENGTH: usize = 255;
+pub const MAX_PATH_LENGTH: usize = 4096;
+
+// Absolute, normalized path in dfs namespace, e.g. "/dir/file".
+// Root is "/", other paths have no trailing slash and no empty, "." or ".." components.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DfsPath(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidPath {
+    pub path: String,
+    pub reason: String,
+}
+
+impl InvalidPath {
+    fn new(path: &str, reason: &str) -> Self {
+        InvalidPath {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for InvalidPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid path: {:?}, {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for InvalidPath {}
+
+impl DfsPath {
+    // Validates path and removes duplicate and trailing slashes
+    pub fn parse(path: &str) -> Result<DfsPath, InvalidPath> {
+        if path.is_empty() {
+            return Err(InvalidPath::new(path, "path is empty"));
+        }
+
+        if !path.starts_with('/') {
+            return Err(InvalidPath::new(path, "path should start with '/'"));
+        }
+
+        if path.contains('\0') {
+            return Err(InvalidPath::new(path, "path contains NUL byte"));
+        }
+
+        let mut normalized = String::with_capacity(path.len());
+
+        for name in path.split('/').filter(|name| !name.is_empty()) {
+            if name == "." || name == ".." {
+                return Err(InvalidPath::new(
+                    path,
+                    "path contains '.' or '..' component",
+                ));
+            }
+
+            if name.len() > MAX_NAME_LENGTH {
+                return Err(InvalidPath::new(path, "name is too long"));
+            }
+
+            normalized.push('/');
+            normalized.push_str(name);
+        }
+
+        if normalized.is_empty() {
+            normalized.push('/');
+        }
+
+        if normalized.len() > MAX_PATH_LENGTH {
+            return Err(InvalidPath::new(path, "path is too long"));
+        }
+
+        Ok(DfsPath(normalized))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.0 == "/"
+    }
+
+    // Names of nodes from root to last one, root has no components
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split('/').filter(|name| !name.is_empty())
+    }
+
+    // Returns None for root
+    pub fn parent(&self) -> Option<DfsPath> {
+        if self.is_root() {
+            return None;
+        }
+
+        match self.0.rfind('/') {
+            Some(0) => Some(DfsPath("/".to_string())),
+            Some(position) => Some(DfsPath(self.0[..position].to_string())),
+            None => None,
+        }
+    }
+
+    // Returns None for root
+    pub fn file_name(&self) -> Option<&str> {
+        self.components().last()
+    }
+}
+
+impl Display for DfsPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_should_be_normalized() {
+        assert_eq!(DfsPath::parse("/").unwrap().as_str(), "/");
+        assert_eq!(DfsPath::parse("//").unwrap().as_str(), "/");
+        assert_eq!(DfsPath::parse("//a").unwrap().as_str(), "/a");
+        assert_eq!(DfsPath::parse("/a//b/").unwrap().as_str(), "/a/b");
+        assert_eq!(DfsPath::parse("/a/b///").unwrap().as_str(), "/a/b");
+    }
+
+    #[test]
+    fn invalid_path_should_be_rejected() {
+        let too_long_name = format!("/{}", "a".repeat(MAX_NAME_LENGTH + 1));
+        let too_long_path = "/a".repeat(MAX_PATH_LENGTH);
+
+        for path in [
+            "",
+            "a/b",
+            "/a/./b",
+            "/a/..",
+            "/a\0b",
+            &too_long_name,
+            &too_long_path,
+        ] {
+            assert!(
+                DfsPath::parse(path).is_err(),
+                "{:?} should be rejected",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn path_should_be_split_into_components() {
+        let path = DfsPath::parse("/a/b/c").unwrap();
+
+        assert_eq!(path.components().collect::<Vec<_>>(), vec!["a", "b", "c"]);
+        assert_eq!(path.file_name(), Some("c"));
+        assert_eq!(path.parent().unwrap().as_str(), "/a/b");
+        assert_eq!(
+            DfsPath::parse("/a").unwrap().parent().unwrap().as_str(),
+            "/"
+        );
+
+        let root = DfsPath::parse("/").unwrap();
+
+        assert_eq!(root.components().count(), 0);
+        assert_eq!(root.parent(), None);
+        assert_eq!(root.file_name(), None);
+    }
+}
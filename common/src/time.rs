@@ -0,0 +1,11 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Times sent between servers, e.g. lease expiration, are milliseconds since unix epoch
+pub fn to_unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_millis() as u64)
+}
+
+pub fn from_unix_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
@@ -5,6 +5,8 @@ use serde::Deserialize;
 pub struct Settings {
     pub master_port: u16,
     pub master_host: String,
+    // Size of chunks file is split into during upload
+    pub chunk_size: usize,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
@@ -0,0 +1,110 @@
+use std::fmt::{self, Display};
+
+use common::{
+    path::InvalidPath,
+    shared::{ErrorCode, ErrorDetails},
+};
+use prost::Message;
+use tonic::{Code, Status};
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    NotADirectory(String),
+    IsADirectory(String),
+    AlreadyExists(String),
+    DirectoryNotEmpty(String),
+    InvalidPath { path: String, reason: String },
+    NoChunkServers,
+    // Master could not grant lease, message describes chunk
+    LeaseUnavailable(String),
+    // Request rejected before it was sent, message describes argument
+    InvalidArgument(String),
+    // Record larger than quarter of chunk can't be appended
+    RecordTooLarge(usize),
+    // None of replicas returned chunk
+    ChunkUnavailable(u64),
+    // Replica failed checksum verification, message describes chunk
+    ChunkCorrupted(String),
+    // Master failed to persist metadata
+    Internal(String),
+    // Rpc failed without error details, e.g. chunk server error
+    Rpc(Status),
+    Transport(tonic::transport::Error),
+    InvalidResponse(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(path) => write!(f, "No such file or directory: {}", path),
+            Error::NotADirectory(path) => write!(f, "Not a directory: {}", path),
+            Error::IsADirectory(path) => write!(f, "Is a directory: {}", path),
+            Error::AlreadyExists(path) => write!(f, "Already exists: {}", path),
+            Error::DirectoryNotEmpty(path) => write!(f, "Directory not empty: {}", path),
+            Error::InvalidPath { path, reason } => {
+                write!(f, "Invalid path: {:?}, {}", path, reason)
+            }
+            Error::NoChunkServers => write!(f, "No chunk servers available"),
+            Error::LeaseUnavailable(message) => write!(f, "Lease unavailable: {}", message),
+            Error::InvalidArgument(message) => write!(f, "Invalid argument: {}", message),
+            Error::RecordTooLarge(size) => write!(f, "Record too large: {} bytes", size),
+            Error::ChunkUnavailable(chunk_handle) => {
+                write!(f, "Chunk: {} unavailable on all replicas", chunk_handle)
+            }
+            Error::ChunkCorrupted(message) => write!(f, "Chunk corrupted: {}", message),
+            Error::Internal(message) => write!(f, "Master server error: {}", message),
+            Error::Rpc(status) => write!(f, "Rpc failed: {}", status.message()),
+            Error::Transport(e) => write!(f, "Connection failed: {}", e),
+            Error::InvalidResponse(message) => write!(f, "Invalid response: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Rebuilds typed error from details attached by master
+impl From<Status> for Error {
+    fn from(status: Status) -> Self {
+        let details = match ErrorDetails::decode(status.details()) {
+            Ok(details) if !status.details().is_empty() => details,
+            // Chunk servers report corruption only with status code
+            _ if status.code() == Code::DataLoss => {
+                return Error::ChunkCorrupted(status.message().to_string())
+            }
+            _ => return Error::Rpc(status),
+        };
+
+        let ErrorDetails { code, path, reason } = details;
+
+        match ErrorCode::try_from(code) {
+            Ok(ErrorCode::NotFound) => Error::NotFound(path),
+            Ok(ErrorCode::NotADirectory) => Error::NotADirectory(path),
+            Ok(ErrorCode::IsADirectory) => Error::IsADirectory(path),
+            Ok(ErrorCode::AlreadyExists) => Error::AlreadyExists(path),
+            Ok(ErrorCode::DirectoryNotEmpty) => Error::DirectoryNotEmpty(path),
+            Ok(ErrorCode::InvalidPath) => Error::InvalidPath { path, reason },
+            Ok(ErrorCode::NoChunkServers) => Error::NoChunkServers,
+            Ok(ErrorCode::LeaseUnavailable) => {
+                Error::LeaseUnavailable(status.message().to_string())
+            }
+            Ok(ErrorCode::Internal) => Error::Internal(status.message().to_string()),
+            Ok(ErrorCode::Unknown) | Err(_) => Error::Rpc(status),
+        }
+    }
+}
+
+impl From<InvalidPath> for Error {
+    fn from(error: InvalidPath) -> Self {
+        Error::InvalidPath {
+            path: error.path,
+            reason: error.reason,
+        }
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(error: tonic::transport::Error) -> Self {
+        Error::Transport(error)
+    }
+}
@@ -1,105 +1,95 @@
-use bytes::Bytes;
+use std::{
+    cmp::min,
+    env,
+    io::{self, Write},
+    process,
+};
+
+use bytes::{Bytes, BytesMut};
 use tonic::Request;
+use uuid::Uuid;
 
+use common::chunk_server::client_service_client::ClientServiceClient as ChunkServerClient;
+use common::chunk_server::{
+    CommitChunkRequest, PushDataRequest, RecordAppendRequest, RecordAppendResponse,
+    RetrieveChunkRequest, WriteChunkRequest,
+};
 use common::master_server::client_service_client::ClientServiceClient;
-use common::master_server::{CreateFileRequest, LsRequest, MkdirRequest};
+use common::master_server::{
+    AllocateChunkRequest, ChunkMetadata, ChunkServerInfo, ChunkServerState, CreateFileRequest,
+    DeleteFileRequest, DeletedFile, ListChunkServersRequest, ListDeletedRequest, LsRequest,
+    MkdirRequest, OpenFileRequest, RenameRequest, RestoreFileRequest, RmdirRequest, StatRequest,
+    StatResponse,
+};
+use common::path::DfsPath;
+use common::time::from_unix_millis;
+use common::{max_record_size, MESSAGE_SIZE};
 
 use crate::config::get_configuration;
+use crate::error::Error;
 
 mod config;
+mod error;
+
+// Attempts of record append on the same chunk before giving up
+const MAX_APPEND_ATTEMPTS: usize = 3;
+// Attempts of write to the same chunk before giving up
+const MAX_WRITE_ATTEMPTS: usize = 3;
+
+// Chunks of open file ordered by chunk index.
+// Mutations lease chunks they touch with allocate_chunk.
+struct FileHandle {
+    path: DfsPath,
+    chunks: Vec<ChunkMetadata>,
+}
 
-// enum Mode {
-//     Read,
-//     Write,
-// }
+impl FileHandle {
+    // File can have gaps in chunk indexes, missing chunk reads as zeros
+    fn chunk(&self, chunk_index: u64) -> Option<&ChunkMetadata> {
+        self.chunks
+            .binary_search_by_key(&chunk_index, |chunk| chunk.chunk_index)
+            .ok()
+            .map(|position| &self.chunks[position])
+    }
 
-// // For now, but should be taken from open file repsonse
-// struct FileHandle {
-//     path: String,
-//     chunks: Option<HashMap<String, String>>,
-// }
+    // Chunks up to last allocated one, including gaps
+    fn chunk_count(&self) -> u64 {
+        self.chunks.last().map_or(0, |chunk| chunk.chunk_index + 1)
+    }
+}
 
 struct Client {
     master_address: String,
+    chunk_size: usize,
 }
 
 impl Client {
-    fn new(master_address: &str) -> Self {
+    fn new(master_address: &str, chunk_size: usize) -> Self {
         Client {
             master_address: master_address.to_string(),
+            chunk_size,
         }
     }
 
-    // // File Handle:
-    // // Mode:Read -> Status and List of chunks with locations
-    // // Mode:Write -> Status
-    // async fn open(&self, path: &str, mode: Mode) -> Result<FileHandle, Box<dyn std::error::Error>> {
-    //     let request = Request::new(OpenFileRequest { path, mode });
-
-    //     let master_client = MasterServiceClient::connect(self.master_address)
-    //         .await
-    //         .expect("Client should connect to master server");
-
-    //     // TODO: Update proto
-    //     let response = master_client
-    //         .open_file(request)
-    //         .await
-    //         .expect("Master should return file handle")
-    //         .into_inner();
-
-    //     let chunks = match mode {
-    //         Mode::Read => Some(response.chunks),
-    //         Mode::Write => None,
-    //     };
-
-    //     let handle = FileHandle {
-    //         path: path.to_string(),
-    //         chunks,
-    //     };
-
-    //     Ok(handle)
-    // }
-
-    // // fn get_metadata(path: &str) -> Result<Metadata, FileSystemError> {
-    // //     todo!()
-    // // }
+    // File Handle: list of chunks with locations, mutations get primaries by allocate_chunk
+    async fn open(&self, path: &str) -> Result<FileHandle, Error> {
+        let path = DfsPath::parse(path)?;
 
-    // async fn read(
-    //     file_handle: &FileHandle,
-    //     offset: usize,
-    //     length: usize,
-    // ) -> Result<Vec<u8>, std::io::Error> {
-    //     let chunks = match file_handle.chunks {
-    //         Some(chunks) => chunks,
-    //         None => {
-    //             return Err(Error::new(
-    //                 std::io::ErrorKind::Other,
-    //                 "Data about chunks is missing.",
-    //             ))
-    //         }
-    //     };
-
-    //     // Assumption for now:
-    //     // offset = 0
-    //     // length = max
-
-    //     // TODO: probably should be parralell
-
-    //     for (handle, address) in chunks.iter() {
-
-    //         // TODO: connect to chunk server and get data
-    //     }
-
-    //     Ok(vec![])
-    // }
-
-    // // fn write(
-    // //     file_handle: &FileHandle,
-    // //     offset: usize,
-    // //     data: &[u8],
-    // // ) -> Result<usize, FileSystemError> {
-    // //     todo!()
-    // // }
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let request = Request::new(OpenFileRequest {
+            file_path: path.to_string(),
+        });
+
+        // Chunks are ordered by chunk index
+        let chunks = master_client
+            .open_file(request)
+            .await?
+            .into_inner()
+            .chunks_metadata;
+
+        Ok(FileHandle { path, chunks })
+    }
 
     // // fn append(file_handle: &FileHandle, data: &[u8]) -> Result<usize, FileSystemError> {
     // //     todo!()
@@ -109,201 +99,686 @@ impl Client {
     // //     todo!()
     // // }
 
-    pub async fn mkdir(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut master_client = ClientServiceClient::connect(self.master_address.clone())
-            .await
-            .expect("Client should connect to master server");
+    pub async fn mkdir(&self, path: &str) -> Result<(), Error> {
+        // Invalid path is rejected before connecting to master
+        let path = DfsPath::parse(path)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
 
         let mkdir_request = Request::new(MkdirRequest {
-            path: path.to_owned(),
+            path: path.to_string(),
+        });
+
+        master_client.mkdir(mkdir_request).await?;
+
+        Ok(())
+    }
+
+    // Recursive delete removes whole subtree, its files can be restored until they are purged
+    pub async fn rmdir(&self, path: &str, recursive: bool) -> Result<(), Error> {
+        let path = DfsPath::parse(path)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let rmdir_request = Request::new(RmdirRequest {
+            path: path.to_string(),
+            recursive,
         });
 
-        let mkdir_response = master_client
-            .mkdir(mkdir_request)
-            .await
-            .expect("Master server should return empty response");
+        master_client.rmdir(rmdir_request).await?;
 
         Ok(())
     }
 
-    pub async fn ls(&self, path: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-        let mut master_client = ClientServiceClient::connect(self.master_address.clone())
-            .await
-            .expect("Client should connect to master server");
+    pub async fn ls(&self, path: &str) -> Result<Vec<String>, Error> {
+        let path = DfsPath::parse(path)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
 
         let ls_request = Request::new(LsRequest {
-            path: path.to_owned(),
+            path: path.to_string(),
         });
 
-        let ls_response = master_client
-            .ls(ls_request)
-            .await
-            .expect("Master server should return list of nodes in directory");
+        let ls_response = master_client.ls(ls_request).await?;
 
         Ok(ls_response.into_inner().content)
     }
 
-    pub async fn create_file(&self, file_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let mut master_client = ClientServiceClient::connect(self.master_address.clone())
-            .await
-            .expect("Client should connect to master server");
+    pub async fn list_chunk_servers(&self) -> Result<Vec<ChunkServerInfo>, Error> {
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let request = Request::new(ListChunkServersRequest {});
+
+        let response = master_client.list_chunk_servers(request).await?;
+
+        Ok(response.into_inner().servers)
+    }
+
+    pub async fn create_file(&self, file_path: &str) -> Result<(), Error> {
+        let file_path = DfsPath::parse(file_path)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
 
         let create_file_request = Request::new(CreateFileRequest {
-            file_path: file_path.to_owned(),
+            file_path: file_path.to_string(),
         });
 
-        let create_file_response = master_client
-            .create_file(create_file_request)
-            .await
-            .expect("Master server should return empty response");
+        master_client.create_file(create_file_request).await?;
 
         Ok(())
     }
 
-    pub async fn upload_file(
-        &self,
-        file_name: &str,
-        data: Bytes,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        // TODO: Config
-        let chunk_size = 64 * 1024 * 1024;
+    pub async fn upload_file(&self, file_path: &str, data: Bytes) -> Result<(), Error> {
+        let file_path = DfsPath::parse(file_path)?;
+
+        self.create_file(file_path.as_str()).await?;
 
-        // 1. OpenFile for writing (path/filename) -> Master (file_handle)
+        let chunks = split_into_chunks(self.chunk_size, data);
 
-        // 2. Split data into chunks
+        for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+            let chunk_metadata = self.allocate_chunk(&file_path, chunk_index as u64).await?;
 
-        // 3. Allocate chunk () -> Master (chunk_handle, Vec<Chunk servers (annotate primary)>)
+            if chunk_metadata.locations.is_empty() {
+                return Err(Error::NoChunkServers);
+            }
 
-        // 4. Stream chunk data to primary with list of secondaries
+            if chunk_metadata.primary.is_empty() {
+                return Err(Error::InvalidResponse(
+                    "Master server should return primary of chunk".to_string(),
+                ));
+            }
 
-        // let chunks = split_into_chunks(chunk_size, data);
+            let data_id = Uuid::new_v4().to_string();
 
-        // let mut master_client = MasterServiceClient::connect(self.master_address.clone())
-        //     .await
-        //     .expect("Client should connect to master server");
+            // Data is sent once and forwarded between replicas,
+            // primary writes it when all of them have it
+            push_data(&chunk_metadata.locations, &data_id, chunk).await?;
+            commit_chunk(
+                &chunk_metadata.primary,
+                chunk_metadata.chunk_handle,
+                &data_id,
+            )
+            .await?;
+        }
 
-        // for (id, chunk) in chunks.iter().enumerate() {
-        //     let request = Request::new(UploadFileRequest {
-        //         file_name: file_name.to_string(),
-        //         chunk_id: id.to_string(),
-        //     });
+        Ok(())
+    }
 
-        //     let upload_file_response = master_client
-        //         .upload_file(request)
-        //         .await
-        //         .expect("Master server should return response with chunk location");
+    pub async fn get_file(&self, file_path: &str) -> Result<Bytes, Error> {
+        let file_handle = self.open(file_path).await?;
 
-        //     println!("Uplad file response: {:?}", upload_file_response);
+        let mut file_data = BytesMut::new();
 
-        //     let response = upload_file_response.into_inner();
+        for chunk_metadata in file_handle.chunks.iter() {
+            let chunk_data = read_chunk(chunk_metadata, 0, 0).await?;
+
+            // Missing chunk or chunk not written to the end before this one is a hole,
+            // it reads as zeros
+            file_data.resize(chunk_metadata.chunk_index as usize * self.chunk_size, 0);
+            file_data.extend_from_slice(&chunk_data);
+        }
+
+        Ok(file_data.freeze())
+    }
 
-        //     for chunk_location in response.chunk_locations {
-        //         let address = chunk_location.address.clone();
-        //         // let address = format!("http://{}", chunk_location.address.clone());
-        //         let chunk_id = response.chunk_id.to_string();
+    // Reads at most length bytes from offset in file, less is returned at end of file.
+    // Only chunks covering the range are fetched, all of them in parallel.
+    pub async fn read_at(&self, file_path: &str, offset: u64, length: u64) -> Result<Bytes, Error> {
+        let file_handle = self.open(file_path).await?;
 
-        //         println!("Connecting to chunk server with address: {:?}", address);
+        let chunk_size = self.chunk_size as u64;
+        let end = offset.saturating_add(length);
 
-        //         let mut chunk_client = ChunkServiceClient::connect(address)
-        //             .await
-        //             .expect("Client should connect to chunk server");
+        let mut reads = Vec::new();
+        let mut position = offset;
 
-        //         let request = Request::new(StoreChunkRequest {
-        //             chunk: Some(ChunkData {
-        //                 chunk_id,
-        //                 data: chunk.to_vec(),
-        //             }),
-        //         });
+        while position < end {
+            let chunk_index = position / chunk_size;
 
-        //         // TODO: Grpc max size 4mb
+            if chunk_index >= file_handle.chunk_count() {
+                break;
+            }
 
-        //         let write_chunk_response = chunk_client
-        //             .store_chunk(request)
-        //             .await
-        //             .expect("Chunk server should return response with status");
+            let chunk_offset = position % chunk_size;
+            let chunk_length = (chunk_size - chunk_offset).min(end - position);
+
+            // Chunk missing in the middle of file is a hole, nothing is fetched for it
+            let read = file_handle
+                .chunk(chunk_index)
+                .cloned()
+                .map(|chunk_metadata| {
+                    tokio::spawn(async move {
+                        read_chunk(&chunk_metadata, chunk_offset, chunk_length).await
+                    })
+                });
+
+            reads.push((chunk_index, (read, chunk_length)));
+            position += chunk_length;
+        }
+
+        let mut data = BytesMut::new();
+
+        for (chunk_index, (read, chunk_length)) in reads {
+            let chunk_data = match read {
+                Some(read) => read.await.expect("Chunk read should not panic")?,
+                None => Vec::new(),
+            };
+
+            data.extend_from_slice(&chunk_data);
+
+            // Last chunk shorter than requested range is end of file,
+            // any other is a hole left by write past its end and reads as zeros
+            if (chunk_data.len() as u64) < chunk_length {
+                if chunk_index + 1 == file_handle.chunk_count() {
+                    break;
+                }
+
+                data.resize(data.len() + (chunk_length as usize - chunk_data.len()), 0);
+            }
+        }
+
+        Ok(data.freeze())
+    }
+
+    // Writes data at offset in file, missing chunks are allocated first, so file is extended
+    // and gap before offset reads as zeros. Every chunk is written through its primary,
+    // so concurrent writes to the same range end in the same order on every replica.
+    // Write spanning multiple chunks is not atomic, it can be interleaved with other writers.
+    pub async fn write(&self, file_path: &str, offset: u64, data: Bytes) -> Result<(), Error> {
+        // Empty write does not extend file
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let end = offset.checked_add(data.len() as u64).ok_or_else(|| {
+            Error::InvalidArgument(format!(
+                "Write at offset: {} of size: {} exceeds maximum file size",
+                offset,
+                data.len()
+            ))
+        })?;
+
+        // Only written chunks need primary, they are returned with lease by allocation
+        let file_handle = self.open(file_path).await?;
+
+        let chunk_size = self.chunk_size as u64;
+
+        // Chunks between end of file and written range are allocated too,
+        // so file size covers the gap
+        for chunk_index in file_handle.chunk_count()..offset / chunk_size {
+            self.allocate_chunk(&file_handle.path, chunk_index).await?;
+        }
+
+        let mut position = offset;
+
+        while position < end {
+            let chunk_index = position / chunk_size;
+            let chunk_offset = position % chunk_size;
+            let chunk_length = (chunk_size - chunk_offset).min(end - position);
+
+            let start = (position - offset) as usize;
+            let chunk_data = data.slice(start..start + chunk_length as usize);
+
+            let mut chunk_metadata = self.allocate_chunk(&file_handle.path, chunk_index).await?;
+            let mut attempts = 0;
+
+            loop {
+                match write_chunk(&chunk_metadata, chunk_offset, chunk_data.clone()).await {
+                    Ok(()) => break,
+                    Err(e) if attempts + 1 < MAX_WRITE_ATTEMPTS => {
+                        eprintln!(
+                            "Failed to write chunk: {}, because: {}, retrying",
+                            chunk_metadata.chunk_handle, e
+                        );
+
+                        // Lease could expire or move to other replica
+                        chunk_metadata =
+                            self.allocate_chunk(&file_handle.path, chunk_index).await?;
+                        attempts += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+
+            position += chunk_length;
+        }
+
+        Ok(())
+    }
+
+    // Appends record at offset chosen by primary and returns offset of record in file.
+    // Record is written at least once, failed attempt can leave duplicate or padding in file.
+    pub async fn append(&self, file_path: &str, record: Bytes) -> Result<u64, Error> {
+        if record.len() as u64 > max_record_size(self.chunk_size as u64) {
+            return Err(Error::RecordTooLarge(record.len()));
+        }
+
+        // Only last chunk needs primary, it is returned with lease by allocation
+        let file_handle = self.open(file_path).await?;
+
+        let mut chunk_index = file_handle.chunk_count().saturating_sub(1);
+        let mut chunk_metadata = self.allocate_chunk(&file_handle.path, chunk_index).await?;
+
+        let mut attempts = 0;
+
+        loop {
+            let result = record_append(&chunk_metadata, record.clone()).await;
+
+            match result {
+                Ok(response) if response.chunk_full => {
+                    // Chunk was padded, record goes to next one
+                    chunk_index += 1;
+                    chunk_metadata = self.allocate_chunk(&file_handle.path, chunk_index).await?;
+                    attempts = 0;
+                }
+                Ok(response) => {
+                    return Ok(chunk_index * self.chunk_size as u64 + response.offset);
+                }
+                Err(e) if attempts + 1 < MAX_APPEND_ATTEMPTS => {
+                    eprintln!(
+                        "Failed to append record to chunk: {}, because: {}, retrying",
+                        chunk_metadata.chunk_handle, e
+                    );
+
+                    // Lease could expire or move to other replica
+                    chunk_metadata = self.allocate_chunk(&file_handle.path, chunk_index).await?;
+                    attempts += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    // Returns existing chunk if it was already allocated, e.g. by other appender
+    async fn allocate_chunk(
+        &self,
+        file_path: &DfsPath,
+        chunk_index: u64,
+    ) -> Result<ChunkMetadata, Error> {
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let request = Request::new(AllocateChunkRequest {
+            file_path: file_path.to_string(),
+            chunk_index,
+        });
+
+        master_client
+            .allocate_chunk(request)
+            .await?
+            .into_inner()
+            .chunk_metadata
+            .ok_or_else(|| {
+                Error::InvalidResponse("Master server should return chunk metadata".to_string())
+            })
+    }
+
+    pub async fn delete_file(&self, file_path: &str) -> Result<(), Error> {
+        let file_path = DfsPath::parse(file_path)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let delete_file_request = Request::new(DeleteFileRequest {
+            file_path: file_path.to_string(),
+        });
 
-        //         println!("Write chunk response: {:?}", write_chunk_response);
-        //     }
-        // }
+        master_client.delete_file(delete_file_request).await?;
 
         Ok(())
     }
 
-    pub async fn get_file(&self, file_name: &str) -> Result<Bytes, Box<dyn std::error::Error>> {
-        // let mut master_client = MasterServiceClient::connect(self.master_address.clone())
-        //     .await
-        //     .expect("Client should connect to master server");
+    // Deleted files under path that can still be restored
+    pub async fn list_deleted(&self, path: &str) -> Result<Vec<DeletedFile>, Error> {
+        let path = DfsPath::parse(path)?;
 
-        // let download_file_request = Request::new(DownloadFileRequest {
-        //     file_name: file_name.to_string(),
-        // });
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
 
-        // let download_file_response = master_client
-        //     .download_file(download_file_request)
-        //     .await
-        //     .expect("Master server should return data with chunks locations");
+        let request = Request::new(ListDeletedRequest {
+            path: path.to_string(),
+        });
+
+        let response = master_client.list_deleted(request).await?;
 
-        // let chunks = download_file_response.into_inner().chunks;
+        Ok(response.into_inner().files)
+    }
 
-        // let mut file_data = BytesMut::new();
+    // Size is known to master once mutation is committed, so readers can find end of file
+    pub async fn stat(&self, file_path: &str) -> Result<StatResponse, Error> {
+        let file_path = DfsPath::parse(file_path)?;
 
-        // for chunk_data in chunks {
-        //     let address = chunk_data.chunk_id.to_string();
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let request = Request::new(StatRequest {
+            file_path: file_path.to_string(),
+        });
 
-        //     let mut chunk_client = ChunkServiceClient::connect(address)
-        //         .await
-        //         .expect("Client should connect to chunk server");
+        let response = master_client.stat(request).await?;
 
-        //     let request = Request::new(RetrieveChunkRequest {
-        //         chunk_id: chunk_data.chunk_id.to_string(),
-        //     });
+        Ok(response.into_inner())
+    }
 
-        //     let response = chunk_client
-        //         .retrieve_chunk(request)
-        //         .await
-        //         .expect("Chunk server should return chunk data");
+    pub async fn restore_file(&self, file_path: &str) -> Result<(), Error> {
+        let file_path = DfsPath::parse(file_path)?;
 
-        //     let chunk_data = response.into_inner().chunk.unwrap().data;
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
 
-        //     file_data.extend_from_slice(&chunk_data);
-        // }
+        let restore_file_request = Request::new(RestoreFileRequest {
+            file_path: file_path.to_string(),
+        });
 
-        // let file_data = file_data.freeze();
+        master_client.restore_file(restore_file_request).await?;
 
-        // Ok(file_data)
-        Ok(Bytes::new())
+        Ok(())
     }
 
-    pub async fn delete_file(&self, file_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-        todo!()
+    // File written under temporary name can be published in single step
+    pub async fn rename(&self, src: &str, dst: &str, overwrite: bool) -> Result<(), Error> {
+        let src = DfsPath::parse(src)?;
+        let dst = DfsPath::parse(dst)?;
+
+        let mut master_client = ClientServiceClient::connect(self.master_address.clone()).await?;
+
+        let rename_request = Request::new(RenameRequest {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            overwrite,
+        });
+
+        master_client.rename(rename_request).await?;
+
+        Ok(())
     }
 }
 
 fn split_into_chunks(chunk_size: usize, data: Bytes) -> Vec<Bytes> {
-    data.chunks(chunk_size)
-        .map(|chunk| Bytes::copy_from_slice(chunk))
+    (0..data.len())
+        .step_by(chunk_size)
+        .map(|start| data.slice(start..min(start + chunk_size, data.len())))
         .collect()
 }
 
+// Streams data to first location, which forwards it along the rest of them.
+// No topology is known, so order from master is used as chain.
+async fn push_data(locations: &[String], data_id: &str, data: Bytes) -> Result<(), Error> {
+    let (first, chain) = locations.split_first().ok_or(Error::NoChunkServers)?;
+
+    let mut chunk_client = ChunkServerClient::connect(format!("http://{}", first)).await?;
+
+    let data_id = data_id.to_owned();
+    let mut chain = chain.to_vec();
+
+    // Empty data is still sent as single message
+    let parts = (0..data.len().max(1))
+        .step_by(MESSAGE_SIZE)
+        .map(move |start| PushDataRequest {
+            data_id: data_id.clone(),
+            // Chain is sent only in first message
+            chain: std::mem::take(&mut chain),
+            data: data[start..min(start + MESSAGE_SIZE, data.len())].to_vec(),
+        });
+
+    chunk_client.push_data(tokio_stream::iter(parts)).await?;
+
+    Ok(())
+}
+
+// Pushes record to replicas and asks primary to append it
+async fn record_append(
+    chunk_metadata: &ChunkMetadata,
+    record: Bytes,
+) -> Result<RecordAppendResponse, Error> {
+    if chunk_metadata.primary.is_empty() {
+        return Err(Error::InvalidResponse(
+            "Master server should return primary of chunk".to_string(),
+        ));
+    }
+
+    let data_id = Uuid::new_v4().to_string();
+
+    push_data(&chunk_metadata.locations, &data_id, record).await?;
+
+    let mut chunk_client =
+        ChunkServerClient::connect(format!("http://{}", chunk_metadata.primary)).await?;
+
+    let request = Request::new(RecordAppendRequest {
+        chunk_handle: chunk_metadata.chunk_handle,
+        data_id,
+        forwarded: false,
+        offset: 0,
+        pad: false,
+        version: 0,
+    });
+
+    let response = chunk_client.record_append(request).await?.into_inner();
+
+    Ok(response)
+}
+
+// Pushes data to replicas and asks primary to write it at offset in chunk
+async fn write_chunk(
+    chunk_metadata: &ChunkMetadata,
+    offset: u64,
+    data: Bytes,
+) -> Result<(), Error> {
+    if chunk_metadata.primary.is_empty() {
+        return Err(Error::InvalidResponse(
+            "Master server should return primary of chunk".to_string(),
+        ));
+    }
+
+    let data_id = Uuid::new_v4().to_string();
+
+    push_data(&chunk_metadata.locations, &data_id, data).await?;
+
+    let mut chunk_client =
+        ChunkServerClient::connect(format!("http://{}", chunk_metadata.primary)).await?;
+
+    let request = Request::new(WriteChunkRequest {
+        chunk_handle: chunk_metadata.chunk_handle,
+        data_id,
+        forwarded: false,
+        offset,
+        version: 0,
+    });
+
+    chunk_client.write_chunk(request).await?;
+
+    Ok(())
+}
+
+async fn commit_chunk(primary: &str, chunk_handle: u64, data_id: &str) -> Result<(), Error> {
+    let mut chunk_client = ChunkServerClient::connect(format!("http://{}", primary)).await?;
+
+    let request = Request::new(CommitChunkRequest {
+        chunk_handle,
+        data_id: data_id.to_owned(),
+        forwarded: false,
+        version: 0,
+    });
+
+    chunk_client.commit_chunk(request).await?;
+
+    Ok(())
+}
+
+// Tries replicas one by one until one of them returns data, length 0 reads whole chunk
+async fn read_chunk(
+    chunk_metadata: &ChunkMetadata,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>, Error> {
+    let chunk_handle = chunk_metadata.chunk_handle;
+
+    for location in chunk_metadata.locations.iter() {
+        match retrieve_chunk(location, chunk_handle, offset, length).await {
+            Ok(data) => return Ok(data),
+            Err(e) => eprintln!(
+                "Failed to retrieve chunk: {} from: {}, because: {}",
+                chunk_handle, location, e
+            ),
+        }
+    }
+
+    Err(Error::ChunkUnavailable(chunk_handle))
+}
+
+async fn retrieve_chunk(
+    address: &str,
+    chunk_handle: u64,
+    offset: u64,
+    length: u64,
+) -> Result<Vec<u8>, Error> {
+    let mut chunk_client = ChunkServerClient::connect(format!("http://{}", address)).await?;
+
+    let request = Request::new(RetrieveChunkRequest {
+        chunk_handle,
+        offset,
+        length,
+    });
+
+    let mut stream = chunk_client.retrieve_chunk(request).await?.into_inner();
+
+    let mut data = Vec::new();
+
+    while let Some(response) = stream.message().await? {
+        if let Some(chunk) = response.chunk {
+            data.extend_from_slice(&chunk.data);
+        }
+    }
+
+    Ok(data)
+}
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
     let config = get_configuration().expect("Failed to read configuration");
     let address = format!("http://{}:{}", config.master_host, config.master_port);
 
-    let client = Client::new(&address);
+    let client = Client::new(&address, config.chunk_size);
 
-    client.mkdir("/path/to/new/directory").await.unwrap();
-    client.create_file("/path/to/new/file").await.unwrap();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
 
-    let content1 = client.ls("/path").await.unwrap();
-    let content2 = client.ls("/path/to").await.unwrap();
-    let content3 = client.ls("/path/to/new").await.unwrap();
-    let content4 = client.ls("/path/to/new/directory").await.unwrap();
+    // Errors are printed with Display, so user sees readable message instead of Debug output
+    if let Err(e) = run(&client, &args).await {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
 
-    println!("{:?}", content1);
-    println!("{:?}", content2);
-    println!("{:?}", content3);
-    println!("{:?}", content4);
+async fn run(client: &Client, args: &[&str]) -> Result<(), Box<dyn std::error::Error>> {
+    match args {
+        ["mkdir", path] => client.mkdir(path).await?,
+        ["rmdir", path] => client.rmdir(path, false).await?,
+        ["ls", path] => {
+            for name in client.ls(path).await? {
+                println!("{}", name);
+            }
+        }
+        ["servers"] => {
+            for server in client.list_chunk_servers().await? {
+                let state = ChunkServerState::try_from(server.state)
+                    .map_or("Unknown", |state| state.as_str_name());
+
+                println!(
+                    "{} {} used: {} available: {} chunks: {} last heartbeat: {}s ago",
+                    server.address,
+                    state,
+                    server.used,
+                    server.available,
+                    server.chunk_count,
+                    server.last_heartbeat
+                );
+            }
+        }
+        ["create", file_path] => client.create_file(file_path).await?,
+        ["delete", "-r", path] => client.rmdir(path, true).await?,
+        ["delete", file_path] => client.delete_file(file_path).await?,
+        ["deleted", path] => {
+            for file in client.list_deleted(path).await? {
+                let age = from_unix_millis(file.deleted_at)
+                    .elapsed()
+                    .unwrap_or_default()
+                    .as_secs();
+
+                println!("{} deleted: {}s ago", file.file_path, age);
+            }
+        }
+        ["restore", file_path] => client.restore_file(file_path).await?,
+        ["mv", src, dst] => client.rename(src, dst, false).await?,
+        ["mv", "-f", src, dst] => client.rename(src, dst, true).await?,
+        ["stat", file_path] => {
+            let stat = client.stat(file_path).await?;
+            let age = |time| {
+                from_unix_millis(time)
+                    .elapsed()
+                    .unwrap_or_default()
+                    .as_secs()
+            };
+
+            println!(
+                "{} size: {} chunks: {} replication: {} created: {}s ago modified: {}s ago",
+                stat.file_path,
+                stat.size,
+                stat.chunk_count,
+                stat.replication,
+                age(stat.created_at),
+                age(stat.modified_at)
+            );
+
+            for chunk in stat.chunks {
+                println!(
+                    "  chunk: {} size: {} replicas: {}",
+                    chunk.chunk_handle, chunk.size, chunk.replicas
+                );
+            }
+        }
+        ["append", file_path, record] => {
+            let offset = client
+                .append(file_path, Bytes::copy_from_slice(record.as_bytes()))
+                .await?;
+            println!("{}", offset);
+        }
+        ["upload", local_path, file_path] => {
+            let data = tokio::fs::read(local_path).await?;
+            client.upload_file(file_path, Bytes::from(data)).await?;
+        }
+        ["get", file_path, local_path] => {
+            let data = client.get_file(file_path).await?;
+            tokio::fs::write(local_path, data).await?;
+        }
+        ["write", file_path, offset, local_path] => {
+            let data = tokio::fs::read(local_path).await?;
+            client
+                .write(file_path, offset.parse()?, Bytes::from(data))
+                .await?;
+        }
+        ["read", file_path, offset, length] => {
+            let data = client
+                .read_at(file_path, offset.parse()?, length.parse()?)
+                .await?;
+            io::stdout().write_all(&data)?;
+        }
+        _ => {
+            eprintln!("Usage:");
+            eprintln!("  dfs-client mkdir <path>");
+            eprintln!("  dfs-client rmdir <path>");
+            eprintln!("  dfs-client ls <path>");
+            eprintln!("  dfs-client servers");
+            eprintln!("  dfs-client create <file_path>");
+            eprintln!("  dfs-client delete <file_path>");
+            eprintln!("  dfs-client delete -r <path>");
+            eprintln!("  dfs-client deleted <path>");
+            eprintln!("  dfs-client restore <file_path>");
+            eprintln!("  dfs-client mv [-f] <src> <dst>");
+            eprintln!("  dfs-client stat <file_path>");
+            eprintln!("  dfs-client append <file_path> <record>");
+            eprintln!("  dfs-client upload <local_path> <file_path>");
+            eprintln!("  dfs-client get <file_path> <local_path>");
+            eprintln!("  dfs-client write <file_path> <offset> <local_path>");
+            eprintln!("  dfs-client read <file_path> <offset> <length>");
+            process::exit(1);
+        }
+    }
 
     Ok(())
 }
@@ -5,6 +5,28 @@ use serde::Deserialize;
 pub struct Settings {
     pub port: u16,
     pub host: String,
+    // Directory with operation log and checkpoints
+    pub data_path: String,
+    // Seconds between metadata checkpoints
+    pub checkpoint_interval: u64,
+    // Seconds for which primary replica holds lease, extended by heartbeats
+    pub lease_duration: u64,
+    // Seconds between scans for under-replicated chunks
+    pub replication_interval: u64,
+    // Max number of chunks copied at the same time in whole cluster
+    pub max_concurrent_clones: usize,
+    // Seconds between checks of chunk servers heartbeats
+    pub reaper_interval: u64,
+    // Seconds without heartbeat after which server is suspect and no longer gets new chunks
+    pub suspect_timeout: u64,
+    // Seconds without heartbeat after which server is dead and its replicas are dropped
+    pub dead_timeout: u64,
+    // Seconds between garbage collection scans
+    pub gc_interval: u64,
+    // Seconds for which deleted file is kept and can be restored
+    pub gc_grace_period: u64,
+    // Size of chunk in bytes, has to be the same as in chunk servers and clients
+    pub chunk_size: u64,
 }
 
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
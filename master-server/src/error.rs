@@ -1 +1,106 @@
-pub struct Error {}
+use std::fmt::{self, Display};
+
+use common::{
+    path::InvalidPath,
+    shared::{ErrorCode, ErrorDetails},
+};
+use prost::{bytes::Bytes, Message};
+use tonic::{Code, Status};
+
+#[derive(Debug)]
+pub enum Error {
+    NotFound(String),
+    NotADirectory(String),
+    IsADirectory(String),
+    AlreadyExists(String),
+    DirectoryNotEmpty(String),
+    InvalidPath { path: String, reason: String },
+    NoChunkServers,
+    // None of replicas accepted lease for chunk with given handle
+    LeaseUnavailable(u64),
+    // Failure to persist or read metadata
+    Io(std::io::Error),
+}
+
+impl Error {
+    fn code(&self) -> Code {
+        match self {
+            Error::NotFound(_) => Code::NotFound,
+            Error::NotADirectory(_) | Error::IsADirectory(_) | Error::DirectoryNotEmpty(_) => {
+                Code::FailedPrecondition
+            }
+            Error::AlreadyExists(_) => Code::AlreadyExists,
+            Error::InvalidPath { .. } => Code::InvalidArgument,
+            Error::NoChunkServers | Error::LeaseUnavailable(_) => Code::Unavailable,
+            Error::Io(_) => Code::Internal,
+        }
+    }
+
+    fn details(&self) -> ErrorDetails {
+        let (code, path, reason) = match self {
+            Error::NotFound(path) => (ErrorCode::NotFound, path.as_str(), ""),
+            Error::NotADirectory(path) => (ErrorCode::NotADirectory, path.as_str(), ""),
+            Error::IsADirectory(path) => (ErrorCode::IsADirectory, path.as_str(), ""),
+            Error::AlreadyExists(path) => (ErrorCode::AlreadyExists, path.as_str(), ""),
+            Error::DirectoryNotEmpty(path) => (ErrorCode::DirectoryNotEmpty, path.as_str(), ""),
+            Error::InvalidPath { path, reason } => {
+                (ErrorCode::InvalidPath, path.as_str(), reason.as_str())
+            }
+            Error::NoChunkServers => (ErrorCode::NoChunkServers, "", ""),
+            Error::LeaseUnavailable(_) => (ErrorCode::LeaseUnavailable, "", ""),
+            // Io error is reported only as message
+            Error::Io(_) => (ErrorCode::Internal, "", ""),
+        };
+
+        ErrorDetails {
+            code: code.into(),
+            path: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotFound(path) => write!(f, "No such file or directory: {}", path),
+            Error::NotADirectory(path) => write!(f, "Not a directory: {}", path),
+            Error::IsADirectory(path) => write!(f, "Is a directory: {}", path),
+            Error::AlreadyExists(path) => write!(f, "Already exists: {}", path),
+            Error::DirectoryNotEmpty(path) => write!(f, "Directory not empty: {}", path),
+            Error::InvalidPath { path, reason } => {
+                write!(f, "Invalid path: {:?}, {}", path, reason)
+            }
+            Error::NoChunkServers => write!(f, "No chunk servers available"),
+            Error::LeaseUnavailable(chunk_handle) => {
+                write!(f, "No replica accepted lease for chunk: {}", chunk_handle)
+            }
+            Error::Io(e) => write!(f, "Metadata storage error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<InvalidPath> for Error {
+    fn from(error: InvalidPath) -> Self {
+        Error::InvalidPath {
+            path: error.path,
+            reason: error.reason,
+        }
+    }
+}
+
+impl From<Error> for Status {
+    fn from(error: Error) -> Self {
+        let details = Bytes::from(error.details().encode_to_vec());
+
+        Status::with_details(error.code(), error.to_string(), details)
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{fs, path::Path, sync::Arc, time::Duration};
 
 use config::get_configuration;
 use server::run;
@@ -12,6 +12,7 @@ mod config;
 mod error;
 mod server;
 mod storage;
+mod tasks;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -21,9 +22,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let configuration = get_configuration().expect("Failed to read conifguration");
     let address = format!("{}:{}", configuration.host, configuration.port);
 
-    let metadata = Arc::new(Metadata::new());
-
-    let master = MasterServer::new(metadata);
+    let data_path = Path::new(&configuration.data_path);
+    fs::create_dir_all(data_path)?;
+
+    let metadata = Arc::new(Metadata::recover(data_path)?);
+
+    tasks::run_checkpoints(metadata.clone(), configuration.checkpoint_interval);
+    tasks::run_garbage_collection(
+        metadata.clone(),
+        configuration.gc_interval,
+        Duration::from_secs(configuration.gc_grace_period),
+    );
+    tasks::run_reaper(
+        metadata.clone(),
+        configuration.reaper_interval,
+        Duration::from_secs(configuration.suspect_timeout),
+        Duration::from_secs(configuration.dead_timeout),
+    );
+    tasks::run_replication(
+        metadata.clone(),
+        configuration.replication_interval,
+        configuration.max_concurrent_clones,
+    );
+
+    let lease_duration = Duration::from_secs(configuration.lease_duration);
+
+    let master = MasterServer::new(metadata, lease_duration, configuration.chunk_size);
 
     let server = run(master, address)?;
 
@@ -1,5 +1,12 @@
-use common::master_server::{
-    chunk_service_server::ChunkService, HeartbeatRequest, HeartbeatResponse,
+use std::time::SystemTime;
+
+use common::{
+    master_server::{
+        chunk_service_server::ChunkService, HeartbeatRequest, HeartbeatResponse, LeaseExtension,
+        ReportChunkSizeRequest,
+    },
+    shared::EmptyReply,
+    time::to_unix_millis,
 };
 use tonic::{Request, Response, Status};
 use tracing::info;
@@ -20,8 +27,42 @@ impl ChunkService for MasterServer {
             heartbeat_request.server_address
         );
 
+        let server_address = heartbeat_request.server_address.clone();
+        let leased_chunks = heartbeat_request.leased_chunks.clone();
+
+        // Lease on corrupted replica is revoked here, so it is not extended below
         let to_delete = self.metadata.heartbeat_update(heartbeat_request);
 
-        Ok(Response::new(HeartbeatResponse { to_delete }))
+        // Primary keeps lease as long as it sends heartbeats
+        let expiration = SystemTime::now() + self.lease_duration;
+
+        let extended_leases = self
+            .metadata
+            .extend_leases(&server_address, &leased_chunks, expiration)
+            .into_iter()
+            .map(|chunk_handle| LeaseExtension {
+                chunk_handle,
+                expiration: to_unix_millis(expiration),
+            })
+            .collect();
+
+        Ok(Response::new(HeartbeatResponse {
+            to_delete,
+            extended_leases,
+        }))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn report_chunk_size(
+        &self,
+        request: Request<ReportChunkSizeRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let ReportChunkSizeRequest { chunk_handle, size } = request.into_inner();
+
+        info!("Chunk: {} has size: {}", chunk_handle, size);
+
+        self.metadata.report_chunk_size(chunk_handle, size);
+
+        Ok(Response::new(EmptyReply {}))
     }
 }
@@ -1,13 +1,15 @@
 use common::{
     master_server::{
         client_service_server::ClientService, AllocateChunkRequest, AllocateChunkResponse,
-        ChunkMetadata, CloseFileRequest, CreateFileRequest, DeleteFileRequest, LsRequest,
-        LsResponse, MkdirRequest, OpenFileRequest, OpenFileResponse,
+        CloseFileRequest, CreateFileRequest, DeleteFileRequest, DeletedFile,
+        ListChunkServersRequest, ListChunkServersResponse, ListDeletedRequest, ListDeletedResponse,
+        LsRequest, LsResponse, MkdirRequest, OpenFileRequest, OpenFileResponse, RenameRequest,
+        RestoreFileRequest, RmdirRequest, StatRequest, StatResponse,
     },
     shared::EmptyReply,
 };
 use tonic::{Request, Response, Status};
-use tracing::info;
+use tracing::{error, info};
 
 use super::MasterServer;
 
@@ -18,9 +20,20 @@ impl ClientService for MasterServer {
         &self,
         request: Request<OpenFileRequest>,
     ) -> Result<Response<OpenFileResponse>, Status> {
-        let response = Response::new(OpenFileResponse {
-            chunks_metadata: Vec::new(),
-        });
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("Open file request from: {:?} received", client_address);
+
+        let OpenFileRequest { file_path } = request.into_inner();
+
+        let chunks_metadata = self.metadata.open_file(&file_path).map_err(|e| {
+            error!("Failed to open file: {}", e);
+            Status::from(e)
+        })?;
+
+        let response = Response::new(OpenFileResponse { chunks_metadata });
 
         Ok(response)
     }
@@ -28,7 +41,7 @@ impl ClientService for MasterServer {
     #[tracing::instrument(skip(self))]
     async fn close_file(
         &self,
-        request: Request<CloseFileRequest>,
+        _request: Request<CloseFileRequest>,
     ) -> Result<Response<EmptyReply>, Status> {
         let response = Response::new(EmptyReply {});
 
@@ -48,7 +61,10 @@ impl ClientService for MasterServer {
 
         let file_path = request.into_inner().file_path;
 
-        self.metadata.create_file(file_path);
+        self.metadata.create_file(file_path).map_err(|e| {
+            error!("Failed to create file: {}", e);
+            Status::from(e)
+        })?;
 
         let response = Response::new(EmptyReply {});
 
@@ -61,8 +77,8 @@ impl ClientService for MasterServer {
         request: Request<DeleteFileRequest>,
     ) -> Result<Response<EmptyReply>, Status> {
         // Only marks file to delete and hides it
-        // Final delete is during GC scan (interval set in config)
-        // GC deletes metadata and sends it in to_delete list (heartbeat) to chunk_server
+        // Final delete is during GC scan (interval and grace period set in config)
+        // GC deletes metadata and chunks are sent in to_delete list (heartbeat) to chunk_server
 
         let client_address = request
             .remote_addr()
@@ -72,13 +88,122 @@ impl ClientService for MasterServer {
 
         let file_path = request.into_inner().file_path;
 
-        self.metadata.delete_file(file_path);
+        self.metadata.delete_file(file_path).map_err(|e| {
+            error!("Failed to delete file: {}", e);
+            Status::from(e)
+        })?;
 
         let response = Response::new(EmptyReply {});
 
         Ok(response)
     }
 
+    #[tracing::instrument(skip(self))]
+    async fn list_deleted(
+        &self,
+        request: Request<ListDeletedRequest>,
+    ) -> Result<Response<ListDeletedResponse>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("List deleted request from: {:?} received", client_address);
+
+        let path = request.into_inner().path;
+
+        let files = self
+            .metadata
+            .list_deleted(&path)
+            .map_err(|e| {
+                error!("Failed to list deleted files: {}", e);
+                Status::from(e)
+            })?
+            .into_iter()
+            .map(|(file_path, deleted_at)| DeletedFile {
+                file_path,
+                deleted_at,
+            })
+            .collect();
+
+        let response = Response::new(ListDeletedResponse { files });
+
+        Ok(response)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn restore_file(
+        &self,
+        request: Request<RestoreFileRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("Restore file request from: {:?} received", client_address);
+
+        let file_path = request.into_inner().file_path;
+
+        // Fails if file was already purged or its name was reused
+        self.metadata.restore_file(file_path).map_err(|e| {
+            error!("Failed to restore file: {}", e);
+            Status::from(e)
+        })?;
+
+        let response = Response::new(EmptyReply {});
+
+        Ok(response)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rename(
+        &self,
+        request: Request<RenameRequest>,
+    ) -> Result<Response<EmptyReply>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("Rename request from: {:?} received", client_address);
+
+        let RenameRequest {
+            src,
+            dst,
+            overwrite,
+        } = request.into_inner();
+
+        self.metadata.rename(&src, &dst, overwrite).map_err(|e| {
+            error!("Failed to rename: {}", e);
+            Status::from(e)
+        })?;
+
+        let response = Response::new(EmptyReply {});
+
+        Ok(response)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn stat(&self, request: Request<StatRequest>) -> Result<Response<StatResponse>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("Stat request from: {:?} received", client_address);
+
+        let file_path = request.into_inner().file_path;
+
+        let stat = self
+            .metadata
+            .stat(&file_path, self.chunk_size)
+            .map_err(|e| {
+                error!("Failed to stat file: {}", e);
+                Status::from(e)
+            })?;
+
+        let response = Response::new(stat);
+
+        Ok(response)
+    }
+
     #[tracing::instrument(skip(self))]
     async fn allocate_chunk(
         &self,
@@ -90,11 +215,25 @@ impl ClientService for MasterServer {
 
         info!("Allocate chunk request from: {:?} received", client_address);
 
-        let file_path = request.into_inner().file_path;
-       
-        let chunk_id = 1;
+        let AllocateChunkRequest {
+            file_path,
+            chunk_index,
+        } = request.into_inner();
 
-        let chunk_metadata = Some(self.metadata.allocate_chunk(&file_path, chunk_id));
+        let mut chunk_metadata = self
+            .metadata
+            .allocate_chunk(&file_path, chunk_index)
+            .map_err(|e| {
+                error!("Failed to allocate chunk: {}", e);
+                Status::from(e)
+            })?;
+
+        self.ensure_lease(&mut chunk_metadata).await.map_err(|e| {
+            error!("Failed to grant lease: {}", e);
+            Status::from(e)
+        })?;
+
+        let chunk_metadata = Some(chunk_metadata);
 
         let response = Response::new(AllocateChunkResponse { chunk_metadata });
 
@@ -111,7 +250,30 @@ impl ClientService for MasterServer {
 
         let path = request.into_inner().path;
 
-        self.metadata.mkdir(&path);
+        self.metadata.mkdir(&path).map_err(|e| {
+            error!("Failed to create directory: {}", e);
+            Status::from(e)
+        })?;
+
+        let response = Response::new(EmptyReply {});
+
+        Ok(response)
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn rmdir(&self, request: Request<RmdirRequest>) -> Result<Response<EmptyReply>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!("Rmdir request from: {:?} received", client_address);
+
+        let RmdirRequest { path, recursive } = request.into_inner();
+
+        self.metadata.rmdir(&path, recursive).map_err(|e| {
+            error!("Failed to delete directory: {}", e);
+            Status::from(e)
+        })?;
 
         let response = Response::new(EmptyReply {});
 
@@ -128,15 +290,34 @@ impl ClientService for MasterServer {
 
         let path = request.into_inner().path;
 
-        let content = self
-            .metadata
-            .ls(&path)
-            .into_iter()
-            .map(|elem| elem.to_owned())
-            .collect();
+        let content = self.metadata.ls(&path).map_err(|e| {
+            error!("Failed to list directory: {}", e);
+            Status::from(e)
+        })?;
 
         let response = Response::new(LsResponse { content });
 
         Ok(response)
     }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_chunk_servers(
+        &self,
+        request: Request<ListChunkServersRequest>,
+    ) -> Result<Response<ListChunkServersResponse>, Status> {
+        let client_address = request
+            .remote_addr()
+            .expect("Method should provide client address");
+
+        info!(
+            "List chunk servers request from: {:?} received",
+            client_address
+        );
+
+        let servers = self.metadata.list_chunk_servers();
+
+        let response = Response::new(ListChunkServersResponse { servers });
+
+        Ok(response)
+    }
 }
@@ -1,13 +1,26 @@
-use std::{future::Future, sync::Arc};
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use common::{
+    chunk_server::{
+        master_service_client::MasterServiceClient, GrantLeaseRequest, UpdateChunkVersionRequest,
+    },
     master_server::chunk_service_server::ChunkServiceServer,
     master_server::client_service_server::ClientServiceServer,
+    master_server::ChunkMetadata,
+    time::to_unix_millis,
+};
+use tonic::{
+    transport::{self, Server},
+    Request,
 };
-use tonic::transport::{Error, Server};
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+use crate::error::Error;
 use crate::storage::metadata::Metadata;
 
 pub mod chunk_service;
@@ -16,19 +29,146 @@ pub mod client_service;
 #[derive(Debug)]
 pub struct MasterServer {
     metadata: Arc<Metadata>,
+    lease_duration: Duration,
+    // Used to compute file size from sizes of its chunks
+    chunk_size: u64,
 }
 
 impl MasterServer {
     #[tracing::instrument]
-    pub fn new(metadata: Arc<Metadata>) -> Self {
-        MasterServer { metadata }
+    pub fn new(metadata: Arc<Metadata>, lease_duration: Duration, chunk_size: u64) -> Self {
+        MasterServer {
+            metadata,
+            lease_duration,
+            chunk_size,
+        }
     }
+
+    // Sets primary of chunk, lease is granted to first replica with new version if chunk has none.
+    // New lease bumps chunk version, replicas that don't get new version are dropped as stale.
+    // Version is logged only after some replica accepted it, so all replicas can't become stale.
+    async fn ensure_lease(&self, chunk_metadata: &mut ChunkMetadata) -> Result<(), Error> {
+        let chunk_handle = chunk_metadata.chunk_handle;
+
+        if let Some(lease) = self.metadata.get_lease(chunk_handle) {
+            chunk_metadata.primary = lease.primary;
+            return Ok(());
+        }
+
+        let version = self.metadata.get_chunk_version(chunk_handle) + 1;
+
+        let mut updated = Vec::new();
+        let mut failed = Vec::new();
+
+        for location in chunk_metadata.locations.iter() {
+            match update_chunk_version(location, chunk_handle, version).await {
+                Ok(()) => updated.push(location.clone()),
+                Err(e) => {
+                    warn!(
+                        "Failed to update version of chunk: {} on: {}, because: {}",
+                        chunk_handle, location, e
+                    );
+                    failed.push(location);
+                }
+            }
+        }
+
+        // Replicas keep current version, lease can be granted again later
+        if updated.is_empty() {
+            return Err(Error::LeaseUnavailable(chunk_handle));
+        }
+
+        self.metadata.bump_chunk_version(chunk_handle, version)?;
+
+        for location in failed {
+            self.metadata.remove_chunk_location(chunk_handle, location);
+        }
+
+        chunk_metadata.locations = updated;
+
+        // Lease is offered to single replica, if it fails lease expires before next grant
+        let location = chunk_metadata.locations[0].clone();
+        let expiration = SystemTime::now() + self.lease_duration;
+
+        let lease = self
+            .metadata
+            .acquire_lease(chunk_handle, &location, expiration);
+
+        if lease.primary != location {
+            // Lease granted by concurrent request
+            chunk_metadata.primary = lease.primary;
+            return Ok(());
+        }
+
+        let secondaries = chunk_metadata
+            .locations
+            .iter()
+            .filter(|address| **address != location)
+            .cloned()
+            .collect();
+
+        match grant_lease(&location, chunk_handle, expiration, secondaries).await {
+            Ok(()) => {
+                info!("Lease for chunk: {} granted to: {}", chunk_handle, location);
+                chunk_metadata.primary = lease.primary;
+                Ok(())
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to grant lease for chunk: {} to: {}, because: {}",
+                    chunk_handle, location, e
+                );
+
+                // Grant may have reached replica even though request failed,
+                // so record is kept until lease expires and it is not extended
+                self.metadata.revoke_lease(chunk_handle, &location);
+
+                Err(Error::LeaseUnavailable(chunk_handle))
+            }
+        }
+    }
+}
+
+async fn grant_lease(
+    address: &str,
+    chunk_handle: u64,
+    expiration: SystemTime,
+    secondaries: Vec<String>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = MasterServiceClient::connect(format!("http://{}", address)).await?;
+
+    let request = Request::new(GrantLeaseRequest {
+        chunk_handle,
+        expiration: to_unix_millis(expiration),
+        secondaries,
+    });
+
+    client.grant_lease(request).await?;
+
+    Ok(())
+}
+
+async fn update_chunk_version(
+    address: &str,
+    chunk_handle: u64,
+    version: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = MasterServiceClient::connect(format!("http://{}", address)).await?;
+
+    let request = Request::new(UpdateChunkVersionRequest {
+        chunk_handle,
+        version,
+    });
+
+    client.update_chunk_version(request).await?;
+
+    Ok(())
 }
 
 pub fn run(
     master_server: MasterServer,
     address: String,
-) -> Result<impl Future<Output = Result<(), Error>>, Box<dyn std::error::Error>> {
+) -> Result<impl Future<Output = Result<(), transport::Error>>, Box<dyn std::error::Error>> {
     tracing::info!(message = "Starting server.", %address);
 
     let addr = address.parse()?;
@@ -0,0 +1,120 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use super::{
+    namespace::Namespace,
+    operation_log::{list_generations, sync_dir},
+};
+
+const CHECKPOINT_PREFIX: &str = "checkpoint.";
+const TMP_SUFFIX: &str = ".tmp";
+
+// Compact copy of metadata state after applying all logs older than generation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub generation: u64,
+    pub namespace: Namespace,
+    pub filepath_to_chunk_handles: HashMap<String, BTreeMap<u64, u64>>,
+    #[serde(default)]
+    pub chunk_versions: HashMap<u64, u64>,
+    // Handles below it were already given to chunks, also to purged ones
+    #[serde(default)]
+    pub next_chunk_handle: u64,
+}
+
+impl Checkpoint {
+    // Checkpoint is written to temporary file and renamed after it is flushed,
+    // so checkpoint.<generation> is either complete or missing.
+    pub fn write(&self, data_path: &Path) -> Result<(), Error> {
+        let path = checkpoint_path(data_path, self.generation);
+        let tmp_path = data_path.join(format!(
+            "{}{}{}",
+            CHECKPOINT_PREFIX, self.generation, TMP_SUFFIX
+        ));
+
+        let file = File::create(&tmp_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(data_path)?;
+
+        info!("Checkpoint: {:?} written", path);
+
+        Ok(())
+    }
+
+    // Returns newest checkpoint that can be read, broken ones are skipped
+    pub fn load_latest(data_path: &Path) -> Result<Option<Checkpoint>, Error> {
+        remove_tmp_files(data_path)?;
+
+        let generations = list_generations(data_path, CHECKPOINT_PREFIX)?;
+
+        for generation in generations.into_iter().rev() {
+            let path = checkpoint_path(data_path, generation);
+
+            match read_checkpoint(&path) {
+                Ok(checkpoint) if checkpoint.generation == generation => {
+                    info!("Checkpoint: {:?} loaded", path);
+                    return Ok(Some(checkpoint));
+                }
+                Ok(checkpoint) => warn!(
+                    "Skipping checkpoint: {:?}, unexpected generation: {}",
+                    path, checkpoint.generation
+                ),
+                Err(e) => warn!("Skipping invalid checkpoint: {:?}, because: {}", path, e),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+// Removes checkpoints replaced by checkpoint with given generation
+pub fn remove_before(data_path: &Path, generation: u64) -> Result<(), Error> {
+    for old_generation in list_generations(data_path, CHECKPOINT_PREFIX)? {
+        if old_generation < generation {
+            fs::remove_file(checkpoint_path(data_path, old_generation))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn checkpoint_path(data_path: &Path, generation: u64) -> PathBuf {
+    data_path.join(format!("{}{}", CHECKPOINT_PREFIX, generation))
+}
+
+fn read_checkpoint(path: &Path) -> Result<Checkpoint, Error> {
+    let reader = BufReader::new(File::open(path)?);
+
+    serde_json::from_reader(reader).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
+
+// Leftovers of checkpoints interrupted by crash
+fn remove_tmp_files(data_path: &Path) -> Result<(), Error> {
+    for entry in fs::read_dir(data_path)? {
+        let path = entry?.path();
+
+        let is_tmp_checkpoint = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(CHECKPOINT_PREFIX) && name.ends_with(TMP_SUFFIX));
+
+        if is_tmp_checkpoint {
+            warn!("Removing incomplete checkpoint: {:?}", path);
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
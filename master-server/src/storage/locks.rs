@@ -0,0 +1,143 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Condvar, Mutex},
+};
+
+use common::path::DfsPath;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LockMode {
+    Read,
+    Write,
+}
+
+// Read/write locks on namespace paths, taken like in GFS master. Operation on
+// /d1/d2/leaf takes read locks on /, /d1 and /d1/d2 and read or write lock on leaf.
+// Read lock on directory keeps it from being deleted or replaced while files are
+// created in it in parallel, write lock on leaf serializes operations on the same name.
+//
+// Locks exist only while they are held or awaited, so namespace can be of any size.
+#[derive(Debug, Default)]
+pub struct PathLocks {
+    held: Mutex<HashMap<String, Holders>>,
+    released: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct Holders {
+    readers: usize,
+    writer: bool,
+}
+
+impl Holders {
+    fn is_free_for(&self, mode: LockMode) -> bool {
+        match mode {
+            LockMode::Read => !self.writer,
+            LockMode::Write => !self.writer && self.readers == 0,
+        }
+    }
+}
+
+// Releases locks when dropped
+#[must_use]
+#[derive(Debug)]
+pub struct PathGuard<'a> {
+    locks: &'a PathLocks,
+    paths: Vec<(String, LockMode)>,
+}
+
+impl PathLocks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Read locks on ancestors of path and lock in given mode on path itself
+    pub fn lock(&self, path: &DfsPath, mode: LockMode) -> PathGuard<'_> {
+        self.lock_all(&[(path, mode)])
+    }
+
+    // Locks several paths at once, path needed in both modes is locked for write.
+    // Locks are taken ordered by depth in namespace tree and then lexicographically,
+    // so operations locking overlapping paths can not deadlock.
+    pub fn lock_all(&self, paths: &[(&DfsPath, LockMode)]) -> PathGuard<'_> {
+        let mut modes: BTreeMap<(usize, String), LockMode> = BTreeMap::new();
+
+        for (path, mode) in paths {
+            let mut ancestor = path.parent();
+
+            while let Some(parent) = ancestor {
+                modes
+                    .entry((depth(&parent), parent.to_string()))
+                    .or_insert(LockMode::Read);
+                ancestor = parent.parent();
+            }
+
+            let entry = modes
+                .entry((depth(path), path.to_string()))
+                .or_insert(*mode);
+            *entry = (*entry).max(*mode);
+        }
+
+        let mut guard = PathGuard {
+            locks: self,
+            paths: Vec::with_capacity(modes.len()),
+        };
+
+        for ((_, path), mode) in modes {
+            self.acquire(&path, mode);
+            guard.paths.push((path, mode));
+        }
+
+        guard
+    }
+
+    fn acquire(&self, path: &str, mode: LockMode) {
+        let mut held = self.held.lock().unwrap();
+
+        loop {
+            let holders = held.entry(path.to_string()).or_default();
+
+            if holders.is_free_for(mode) {
+                match mode {
+                    LockMode::Read => holders.readers += 1,
+                    LockMode::Write => holders.writer = true,
+                }
+                return;
+            }
+
+            held = self.released.wait(held).unwrap();
+        }
+    }
+
+    fn release(&self, path: &str, mode: LockMode) {
+        let mut held = self.held.lock().unwrap();
+
+        if let Some(holders) = held.get_mut(path) {
+            match mode {
+                LockMode::Read => holders.readers -= 1,
+                LockMode::Write => holders.writer = false,
+            }
+
+            if holders.readers == 0 && !holders.writer {
+                held.remove(path);
+            }
+        }
+
+        drop(held);
+
+        // Waiters on different paths are woken too, they go back to sleep
+        self.released.notify_all();
+    }
+}
+
+impl Drop for PathGuard<'_> {
+    fn drop(&mut self) {
+        for (path, mode) in self.paths.iter().rev() {
+            self.locks.release(path, *mode);
+        }
+    }
+}
+
+fn depth(path: &DfsPath) -> usize {
+    path.components().count()
+}
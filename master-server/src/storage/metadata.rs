@@ -1,170 +1,1213 @@
 use std::{
-    collections::{HashMap, HashSet},
-    hash::{DefaultHasher, Hash, Hasher},
-    io::{Error, ErrorKind},
-    sync::Mutex,
-    time::Instant,
+    collections::{BTreeMap, HashMap, HashSet},
+    path::Path,
+    sync::{Mutex, RwLock},
+    time::{Duration, Instant, SystemTime},
 };
 
-use common::master_server::{ChunkMetadata, HeartbeatRequest};
+use common::{
+    master_server::{
+        ChunkMetadata, ChunkServerInfo, ChunkServerState, ChunkStat, HeartbeatRequest,
+        StatResponse, StoredChunk,
+    },
+    path::DfsPath,
+    time::to_unix_millis,
+};
+use tracing::{error, info, warn};
 
-use crate::storage::operation_log::OperationLog;
+use crate::error::Error;
+use crate::storage::operation_log::{self, Operation, OperationLog};
 
-use super::namespace::Namespace;
+use super::{
+    checkpoint::{self, Checkpoint},
+    locks::{LockMode, PathLocks},
+    namespace::Namespace,
+};
 
 #[derive(Debug)]
 pub struct ChunkServerStatus {
     pub address: String,
     used: u64,
     available: u64,
-    chunk_handles: HashSet<String>,
+    chunk_handles: HashSet<u64>,
     last_heartbeat: Instant,
+    // Updated by reaper task, heartbeat makes server alive again
+    state: ChunkServerState,
 }
 
 impl ChunkServerStatus {
-    pub fn new(address: String, used: u64, available: u64, chunk_handles: HashSet<String>) -> Self {
+    pub fn new(address: String, used: u64, available: u64, chunk_handles: HashSet<u64>) -> Self {
         ChunkServerStatus {
             address,
             used,
             available,
             chunk_handles,
             last_heartbeat: Instant::now(),
+            state: ChunkServerState::Alive,
         }
     }
+
+    // Replicas on dead server are not used, suspect one is still tried by readers
+    fn is_available(&self) -> bool {
+        self.state != ChunkServerState::Dead
+    }
+}
+
+// Number of replicas of every chunk
+const REPLICATION_FACTOR: usize = 3;
+// Handle of first allocated chunk, 0 is not used so it never means missing handle
+const FIRST_CHUNK_HANDLE: u64 = 1;
+
+// Copy of chunk from one of its replicas to server that does not have it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloneTask {
+    pub chunk_handle: u64,
+    // Current version, clone is not used if chunk got new version in the meantime
+    pub version: u64,
+    pub source: String,
+    pub target: String,
+}
+
+// Filled part of chunk as reported by its replicas
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ChunkSize {
+    size: u64,
+    // Milliseconds since unix epoch
+    modified_at: u64,
+}
+
+// Primary replica of chunk, it orders mutations until lease expires
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub primary: String,
+    pub expiration: SystemTime,
+    // Revoked lease is not extended, but primary can hold it until expiration
+    pub revoked: bool,
+}
+
+impl Lease {
+    fn is_valid(&self) -> bool {
+        self.expiration > SystemTime::now()
+    }
 }
 
+// Locks are always taken in order of fields below, lock may be skipped but never
+// taken while lock later in order is held:
+// path_locks, checkpoint_lock, operation_log, namespace, filepath_to_chunk_handles,
+// chunk_handle_to_file, chunk_servers, chunk_handle_to_chunk_servers, leases,
+// chunk_versions, chunk_sizes, next_chunk_handle.
+// Locks after path_locks are held only for single lookup or update, not for whole operation.
 #[derive(Debug)]
 pub struct Metadata {
-    namespace: Mutex<Namespace>,
-    //TODO: Operation log
+    // Namespace operations lock paths they use for whole operation, so operations
+    // on different files and directories run in parallel. Operations on the same path
+    // are applied and logged in the same order, so replay gives the same state.
+    path_locks: PathLocks,
+    // Mutations hold it for read while they are applied and logged, checkpoint holds it
+    // for write, so it never contains operation that is appended to log after it
+    checkpoint_lock: RwLock<()>,
     operation_log: Mutex<OperationLog>,
-    // stores filename to chunk handles list mapping - updated during alloc
-    filepath_to_chunk_handles: Mutex<HashMap<String, HashSet<u64>>>,
-    // stores chunk handles locations on chunk servers - updated in heartbeat
-    chunk_handle_to_chunk_servers: Mutex<HashMap<String, HashSet<String>>>,
+    pub(super) namespace: Mutex<Namespace>,
+    // stores filename to chunk handles mapping ordered by chunk index - updated during alloc
+    pub(super) filepath_to_chunk_handles: Mutex<HashMap<String, BTreeMap<u64, u64>>>,
+    // stores file owning each chunk, kept in step with filepath_to_chunk_handles.
+    // Not persisted - rebuilt from it on recovery.
+    pub(super) chunk_handle_to_file: Mutex<HashMap<u64, String>>,
     // stores adressess of chunk servers
     pub chunk_servers: Mutex<HashMap<String, ChunkServerStatus>>,
+    // stores chunk handles locations on chunk servers - updated in heartbeat
+    chunk_handle_to_chunk_servers: Mutex<HashMap<u64, HashSet<String>>>,
+    // stores leases granted for chunks, not persisted - after restart master waits for new grants
+    leases: Mutex<HashMap<u64, Lease>>,
+    // stores current version of chunks, chunk without entry has version 0.
+    pub(super) chunk_versions: Mutex<HashMap<u64, u64>>,
+    // stores sizes of chunks, not persisted - rebuilt from heartbeats.
+    chunk_sizes: Mutex<HashMap<u64, ChunkSize>>,
+    // stores handle given to next allocated chunk, restored from checkpoint and log.
+    pub(super) next_chunk_handle: Mutex<u64>,
 }
 
 impl Metadata {
-    pub fn new() -> Self {
+    pub fn new(operation_log: OperationLog) -> Self {
+        let path_locks = PathLocks::new();
+        let checkpoint_lock = RwLock::new(());
+        let operation_log = Mutex::new(operation_log);
         let namespace = Mutex::new(Namespace::new());
-        let operation_log = Mutex::new(OperationLog::new());
         let filepath_to_chunk_handles = Mutex::new(HashMap::new());
+        let chunk_handle_to_file = Mutex::new(HashMap::new());
         let chunk_handle_to_chunk_servers = Mutex::new(HashMap::new());
+        let leases = Mutex::new(HashMap::new());
+        let chunk_versions = Mutex::new(HashMap::new());
+        let chunk_sizes = Mutex::new(HashMap::new());
+        let next_chunk_handle = Mutex::new(FIRST_CHUNK_HANDLE);
         let chunk_servers = Mutex::new(HashMap::new());
 
         Metadata {
-            namespace,
+            path_locks,
+            checkpoint_lock,
             operation_log,
+            namespace,
             filepath_to_chunk_handles,
+            chunk_handle_to_file,
             chunk_handle_to_chunk_servers,
+            leases,
+            chunk_versions,
+            chunk_sizes,
+            next_chunk_handle,
             chunk_servers,
         }
     }
 
-    pub fn mkdir(&self, path: &str) {
-        self.namespace.lock().unwrap().mkdir(path)
+    // Rebuilds metadata from newest checkpoint and operations logged after it
+    pub fn recover(data_path: &Path) -> Result<Self, Error> {
+        let checkpoint = Checkpoint::load_latest(data_path)?;
+        let generation = checkpoint.as_ref().map_or(0, |c| c.generation);
+
+        let (operation_log, operations) = OperationLog::open(data_path, generation)?;
+
+        let metadata = Metadata::new(operation_log);
+
+        if let Some(checkpoint) = checkpoint {
+            // Checkpoint written before counter was persisted has only handles in use
+            let next_chunk_handle = checkpoint
+                .filepath_to_chunk_handles
+                .values()
+                .flat_map(|handles| handles.values())
+                .map(|chunk_handle| chunk_handle.saturating_add(1))
+                .fold(checkpoint.next_chunk_handle, u64::max);
+
+            *metadata.chunk_handle_to_file.lock().unwrap() = checkpoint
+                .filepath_to_chunk_handles
+                .iter()
+                .flat_map(|(file_path, handles)| {
+                    handles
+                        .values()
+                        .map(|chunk_handle| (*chunk_handle, file_path.clone()))
+                })
+                .collect();
+
+            *metadata.namespace.lock().unwrap() = checkpoint.namespace;
+            *metadata.filepath_to_chunk_handles.lock().unwrap() =
+                checkpoint.filepath_to_chunk_handles;
+            *metadata.chunk_versions.lock().unwrap() = checkpoint.chunk_versions;
+            *metadata.next_chunk_handle.lock().unwrap() = next_chunk_handle.max(FIRST_CHUNK_HANDLE);
+        }
+
+        for operation in operations.iter() {
+            metadata.apply(operation)?;
+        }
+
+        info!(
+            "Metadata recovered from checkpoint generation: {}, operations replayed: {}",
+            generation,
+            operations.len()
+        );
+
+        Ok(metadata)
+    }
+
+    // Writes checkpoint of current state and removes operation logs included in it.
+    // Mutations are blocked only while state is copied, serialization happens without locks.
+    pub fn checkpoint(&self) -> Result<(), Error> {
+        let (checkpoint, data_path) = {
+            let _mutations = self.checkpoint_lock.write().unwrap();
+            let mut operation_log = self.operation_log.lock().unwrap();
+
+            if operation_log.is_empty() {
+                // Nothing changed since last checkpoint
+                return Ok(());
+            }
+
+            let generation = operation_log.rotate()?;
+
+            let checkpoint = Checkpoint {
+                generation,
+                namespace: self.namespace.lock().unwrap().clone(),
+                filepath_to_chunk_handles: self.filepath_to_chunk_handles.lock().unwrap().clone(),
+                chunk_versions: self.chunk_versions.lock().unwrap().clone(),
+                next_chunk_handle: *self.next_chunk_handle.lock().unwrap(),
+            };
+
+            (checkpoint, operation_log.data_path().to_path_buf())
+        };
+
+        checkpoint.write(&data_path)?;
+
+        operation_log::remove_before(&data_path, checkpoint.generation)?;
+        checkpoint::remove_before(&data_path, checkpoint.generation)?;
+
+        Ok(())
+    }
+
+    // Paths are normalized before they are logged or used as keys,
+    // so "/dir//file/" and "/dir/file" refer to the same file
+    pub fn mkdir(&self, path: &str) -> Result<(), Error> {
+        let path = DfsPath::parse(path)?;
+        let _locks = self.path_locks.lock(&path, LockMode::Write);
+
+        self.commit(Operation::Mkdir {
+            path: path.to_string(),
+        })
     }
 
-    pub fn ls(&self, path: &str) -> Vec<String> {
-        self.namespace
+    pub fn ls(&self, path: &str) -> Result<Vec<String>, Error> {
+        let path = DfsPath::parse(path)?;
+        let _locks = self.path_locks.lock(&path, LockMode::Read);
+
+        let content = self
+            .namespace
             .lock()
             .unwrap()
-            .ls(path)
+            .ls(path.as_str())?
             .into_iter()
             .map(|elem| elem.to_owned())
-            .collect()
+            .collect();
+
+        Ok(content)
+    }
+
+    pub fn create_file(&self, file_path: String) -> Result<(), Error> {
+        let file_path = DfsPath::parse(&file_path)?;
+        let _locks = self.path_locks.lock(&file_path, LockMode::Write);
+        let created_at = to_unix_millis(SystemTime::now());
+
+        self.commit(Operation::CreateFile {
+            file_path: file_path.to_string(),
+            created_at,
+        })
+    }
+
+    // File is hidden, its chunks are kept until garbage collection purges it
+    pub fn delete_file(&self, file_path: String) -> Result<(), Error> {
+        let file_path = DfsPath::parse(&file_path)?;
+        let _locks = self.path_locks.lock(&file_path, LockMode::Write);
+        let deleted_at = to_unix_millis(SystemTime::now());
+
+        self.commit(Operation::DeleteFile {
+            file_path: file_path.to_string(),
+            deleted_at,
+        })
+    }
+
+    // Files under directory are deleted and purged like deleted files,
+    // directory is hidden until they are purged
+    pub fn rmdir(&self, path: &str, recursive: bool) -> Result<(), Error> {
+        let path = DfsPath::parse(path)?;
+        let _locks = self.path_locks.lock(&path, LockMode::Write);
+        let deleted_at = to_unix_millis(SystemTime::now());
+
+        self.commit(Operation::DeleteDirectory {
+            path: path.to_string(),
+            deleted_at,
+            recursive,
+        })
     }
 
-    pub fn create_file(&self, file_path: String) {
-        self.namespace.lock().unwrap().create_file(&file_path);
+    // Both paths are locked, so create or delete of either of them waits for rename
+    pub fn rename(&self, src: &str, dst: &str, overwrite: bool) -> Result<(), Error> {
+        let src = DfsPath::parse(src)?;
+        let dst = DfsPath::parse(dst)?;
+        let _locks = self
+            .path_locks
+            .lock_all(&[(&src, LockMode::Write), (&dst, LockMode::Write)]);
+
+        self.commit(Operation::Rename {
+            src: src.to_string(),
+            dst: dst.to_string(),
+            overwrite,
+        })
+    }
+
+    // Deleted files under path that were not purged yet, with deletion time in ms since epoch
+    pub fn list_deleted(&self, path: &str) -> Result<Vec<(String, u64)>, Error> {
+        let path = DfsPath::parse(path)?;
+        let _locks = self.path_locks.lock(&path, LockMode::Read);
+
+        self.namespace.lock().unwrap().deleted_files(path.as_str())
+    }
+
+    pub fn restore_file(&self, file_path: String) -> Result<(), Error> {
+        let file_path = DfsPath::parse(&file_path)?;
+        let _locks = self.path_locks.lock(&file_path, LockMode::Write);
+
+        self.commit(Operation::RestoreFile {
+            file_path: file_path.to_string(),
+        })
+    }
+
+    // Purges files deleted longer than grace period ago and returns their paths.
+    // Chunks of purged files are deleted from chunk servers through heartbeats.
+    pub fn collect_garbage(&self, grace_period: Duration) -> Result<Vec<String>, Error> {
+        let deadline = to_unix_millis(SystemTime::now() - grace_period);
+
+        let expired: Vec<(String, u64)> = self
+            .namespace
+            .lock()
+            .unwrap()
+            .deleted_files("/")?
+            .into_iter()
+            .filter(|(_, deleted_at)| *deleted_at <= deadline)
+            .collect();
+
+        let mut purged = Vec::new();
+
+        for (file_path, deleted_at) in expired {
+            let _locks = self
+                .path_locks
+                .lock(&DfsPath::parse(&file_path)?, LockMode::Write);
+
+            match self.commit(Operation::PurgeFile {
+                file_path: file_path.clone(),
+                deleted_at,
+            }) {
+                Ok(()) => purged.push(file_path),
+                // File was restored or replaced by new one in the meantime
+                Err(Error::NotFound(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(purged)
+    }
+
+    // Size of file is end of its last written byte, chunks before it that were not
+    // filled are holes. File without reported chunk sizes has size 0.
+    pub fn stat(&self, file_path: &str, chunk_size: u64) -> Result<StatResponse, Error> {
+        let file_path = DfsPath::parse(file_path)?;
+        let _locks = self.path_locks.lock(&file_path, LockMode::Read);
+
+        let created_at = self
+            .namespace
+            .lock()
+            .unwrap()
+            .created_at(file_path.as_str())?;
+
+        let handles: Vec<(u64, u64)> = match self
+            .filepath_to_chunk_handles
+            .lock()
+            .unwrap()
+            .get(file_path.as_str())
+        {
+            Some(handles) => handles.iter().map(|(i, h)| (*i, *h)).collect(),
+            None => return Err(Error::NotFound(file_path.to_string())),
+        };
+
+        let replicas: Vec<u64> = handles
+            .iter()
+            .map(|(chunk_index, chunk_handle)| {
+                self.get_chunk_metadata(*chunk_index, *chunk_handle)
+                    .locations
+                    .len() as u64
+            })
+            .collect();
+
+        let sizes = self.chunk_sizes.lock().unwrap();
+
+        let mut size = 0;
+        let mut modified_at = created_at;
+        let mut chunks = Vec::new();
+
+        for ((chunk_index, chunk_handle), replicas) in handles.into_iter().zip(replicas) {
+            let chunk = sizes.get(&chunk_handle).copied().unwrap_or(ChunkSize {
+                size: 0,
+                modified_at: 0,
+            });
+
+            if chunk.size > 0 {
+                size = size.max(chunk_index * chunk_size + chunk.size);
+            }
+            modified_at = modified_at.max(chunk.modified_at);
+
+            chunks.push(ChunkStat {
+                chunk_handle,
+                size: chunk.size,
+                replicas,
+            });
+        }
+
+        Ok(StatResponse {
+            file_path: file_path.to_string(),
+            size,
+            chunk_count: chunks.len() as u64,
+            replication: REPLICATION_FACTOR as u64,
+            created_at,
+            modified_at,
+            chunks,
+        })
+    }
 
-        // I should probably prevent overriding existing file
-        self.filepath_to_chunk_handles
+    // Called when primary applied mutation on all replicas. Chunks only grow,
+    // so report delayed behind newer one does not shrink known size.
+    pub fn report_chunk_size(&self, chunk_handle: u64, size: u64) {
+        // Reports for chunks of purged files are ignored, so their sizes are not kept forever
+        let is_owned = self
+            .chunk_handle_to_file
             .lock()
             .unwrap()
-            .insert(file_path, HashSet::new());
+            .contains_key(&chunk_handle);
+
+        if !is_owned {
+            return;
+        }
+
+        let mut sizes = self.chunk_sizes.lock().unwrap();
+
+        let chunk = sizes.entry(chunk_handle).or_insert(ChunkSize {
+            size: 0,
+            modified_at: 0,
+        });
+
+        chunk.size = chunk.size.max(size);
+        chunk.modified_at = to_unix_millis(SystemTime::now());
     }
 
-    pub fn delete_file(&self, file_path: String) {
-        self.namespace.lock().unwrap().delete_file(&file_path);
+    // Returns chunks of file ordered by chunk index with locations of their replicas
+    pub fn open_file(&self, file_path: &str) -> Result<Vec<ChunkMetadata>, Error> {
+        let file_path = DfsPath::parse(file_path)?;
+        let _locks = self.path_locks.lock(&file_path, LockMode::Read);
 
-        // Should i delete it form filepath_to_chunk_handles already
-        // or during GC ?
+        self.namespace
+            .lock()
+            .unwrap()
+            .check_file(file_path.as_str())?;
 
-        // During gc, but access should be limited by check to namespace if file has not been marked as to delete
-        self.filepath_to_chunk_handles
+        let handles = match self
+            .filepath_to_chunk_handles
             .lock()
             .unwrap()
-            .remove(&file_path);
+            .get(file_path.as_str())
+        {
+            Some(handles) => handles.iter().map(|(i, h)| (*i, *h)).collect::<Vec<_>>(),
+            None => return Err(Error::NotFound(file_path.to_string())),
+        };
+
+        let chunks_metadata = handles
+            .into_iter()
+            .map(|(chunk_index, chunk_handle)| self.get_chunk_metadata(chunk_index, chunk_handle))
+            .collect();
+
+        Ok(chunks_metadata)
     }
 
-    pub fn allocate_chunk(&self, file_path: &str, chunk_id: u64) -> ChunkMetadata {
-        // Generate chunk handles
-        let chunk_handle = self.generate_chunk_handle(file_path, chunk_id);
+    pub fn allocate_chunk(
+        &self,
+        file_path: &str,
+        chunk_index: u64,
+    ) -> Result<ChunkMetadata, Error> {
+        let file_path = DfsPath::parse(file_path)?;
+        // Allocations in the same file are serialized
+        let _locks = self.path_locks.lock(&file_path, LockMode::Write);
+        let file_path = file_path.as_str();
+
+        self.namespace.lock().unwrap().check_file(file_path)?;
+
+        // Concurrent appenders that filled the same chunk get the same next chunk
+        if let Some(chunk_handle) = self.get_chunk_handle(file_path, chunk_index)? {
+            return Ok(self.get_chunk_metadata(chunk_index, chunk_handle));
+        }
+
+        let locations = self.get_locations_for_chunk();
+
+        if locations.is_empty() {
+            return Err(Error::NoChunkServers);
+        }
+
+        // Handles are never reused, also by file created again after delete
+        let chunk_handle = self.generate_chunk_handle();
+
+        let operation = Operation::AllocateChunk {
+            file_path: file_path.to_string(),
+            chunk_index,
+            chunk_handle,
+        };
 
-        // Update lookup table
+        self.commit(operation)?;
+
+        // Chosen servers are expected to store chunk, so it can be read before next heartbeat
+        self.chunk_handle_to_chunk_servers
+            .lock()
+            .unwrap()
+            .entry(chunk_handle)
+            .or_default()
+            .extend(locations.iter().cloned());
+
+        // Lease is granted by server after chunk is allocated
+        let chunk_metadata = ChunkMetadata {
+            chunk_handle,
+            locations,
+            primary: String::new(),
+            chunk_index,
+        };
+
+        Ok(chunk_metadata)
+    }
+
+    fn get_chunk_handle(&self, file_path: &str, chunk_index: u64) -> Result<Option<u64>, Error> {
         match self
             .filepath_to_chunk_handles
             .lock()
             .unwrap()
-            .get_mut(file_path)
+            .get(file_path)
         {
-            Some(handles) => {
-                handles.insert(chunk_handle);
+            Some(handles) => Ok(handles.get(&chunk_index).copied()),
+            None => Err(Error::NotFound(file_path.to_string())),
+        }
+    }
 
-                let locations = self.get_locations_for_chunk();
+    // Locations are limited to registered servers, primary is set only if lease is valid
+    fn get_chunk_metadata(&self, chunk_index: u64, chunk_handle: u64) -> ChunkMetadata {
+        let servers = self.chunk_servers.lock().unwrap();
 
-                // TODO: Send Lease Message to one of servers
+        let locations = self
+            .chunk_handle_to_chunk_servers
+            .lock()
+            .unwrap()
+            .get(&chunk_handle)
+            .map(|locations| {
+                locations
+                    .iter()
+                    .filter(|address| servers.get(*address).is_some_and(|s| s.is_available()))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let primary = self
+            .get_lease(chunk_handle)
+            .map(|lease| lease.primary)
+            .unwrap_or_default();
+
+        ChunkMetadata {
+            chunk_handle,
+            locations,
+            primary,
+            chunk_index,
+        }
+    }
+
+    // Returns clones that bring chunks with fewest replicas back to replication factor first.
+    // At most limit clones are returned, so recovery does not take whole network.
+    pub fn plan_replication(&self, limit: usize) -> Vec<CloneTask> {
+        // Chunks of deleted files are re-replicated too, until files are purged
+        let handles: Vec<u64> = self
+            .chunk_handle_to_file
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+
+        let servers = self.chunk_servers.lock().unwrap();
+        let locations_map = self.chunk_handle_to_chunk_servers.lock().unwrap();
+
+        let mut under_replicated: Vec<(u64, Vec<&String>)> = handles
+            .into_iter()
+            .filter_map(|chunk_handle| {
+                let locations: Vec<&String> = locations_map
+                    .get(&chunk_handle)
+                    .map(|locations| {
+                        locations
+                            .iter()
+                            .filter(|address| {
+                                servers.get(*address).is_some_and(|s| s.is_available())
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+
+                if locations.is_empty() {
+                    warn!("Chunk: {} has no replicas left", chunk_handle);
+                    return None;
+                }
+
+                (locations.len() < REPLICATION_FACTOR).then_some((chunk_handle, locations))
+            })
+            .collect();
+
+        // Chunk that lost most replicas goes first
+        under_replicated.sort_by_key(|(chunk_handle, locations)| (locations.len(), *chunk_handle));
+
+        // New replicas are placed on alive servers with greatest available space
+        let mut targets: Vec<&ChunkServerStatus> = servers
+            .values()
+            .filter(|status| status.state == ChunkServerState::Alive)
+            .collect();
+        targets.sort_by_key(|status| std::cmp::Reverse(status.available));
+
+        let mut tasks = Vec::new();
+
+        for (chunk_handle, mut locations) in under_replicated {
+            locations.sort();
 
-                let chunk_metadata = ChunkMetadata {
+            let missing = REPLICATION_FACTOR - locations.len();
+
+            let chunk_targets = targets
+                .iter()
+                .filter(|status| !locations.contains(&&status.address))
+                .take(missing);
+
+            for (i, target) in chunk_targets.enumerate() {
+                if tasks.len() == limit {
+                    return tasks;
+                }
+
+                tasks.push(CloneTask {
                     chunk_handle,
-                    locations,
+                    version: self.get_chunk_version(chunk_handle),
+                    source: locations[i % locations.len()].clone(),
+                    target: target.address.clone(),
+                });
+            }
+        }
+
+        tasks
+    }
+
+    // Records replica created by clone, so it is visible before next heartbeat of target.
+    // Replica cloned before chunk got new version is stale and is not recorded.
+    pub fn add_chunk_location(&self, chunk_handle: u64, address: &str, version: u64) {
+        let mut locations_map = self.chunk_handle_to_chunk_servers.lock().unwrap();
+
+        if version != self.get_chunk_version(chunk_handle) {
+            return;
+        }
+
+        locations_map
+            .entry(chunk_handle)
+            .or_default()
+            .insert(address.to_string());
+    }
+
+    // Drops replica that missed version update
+    pub fn remove_chunk_location(&self, chunk_handle: u64, address: &str) {
+        if let Some(locations) = self
+            .chunk_handle_to_chunk_servers
+            .lock()
+            .unwrap()
+            .get_mut(&chunk_handle)
+        {
+            locations.remove(address);
+        }
+    }
+
+    pub fn get_chunk_version(&self, chunk_handle: u64) -> u64 {
+        self.chunk_versions
+            .lock()
+            .unwrap()
+            .get(&chunk_handle)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    // Records version of chunk after replicas accepted it, so failed update does not
+    // make all replicas stale. Version is logged even if concurrent grant recorded it,
+    // lower version never replaces higher one. Replica with version newer than logged
+    // one is accepted by heartbeat, if master fails before version is logged.
+    pub fn bump_chunk_version(&self, chunk_handle: u64, version: u64) -> Result<(), Error> {
+        self.commit(Operation::BumpChunkVersion {
+            chunk_handle,
+            version,
+        })
+    }
+
+    // Returns lease of chunk if it has not expired
+    pub fn get_lease(&self, chunk_handle: u64) -> Option<Lease> {
+        self.leases
+            .lock()
+            .unwrap()
+            .get(&chunk_handle)
+            .filter(|lease| lease.is_valid())
+            .cloned()
+    }
+
+    // Records lease for given primary unless chunk already has valid lease.
+    // Returns lease that is valid after call, so concurrent grants end with single primary.
+    pub fn acquire_lease(&self, chunk_handle: u64, primary: &str, expiration: SystemTime) -> Lease {
+        let mut leases = self.leases.lock().unwrap();
+
+        match leases.get(&chunk_handle) {
+            Some(lease) if lease.is_valid() => lease.clone(),
+            _ => {
+                let lease = Lease {
+                    primary: primary.to_string(),
+                    expiration,
+                    revoked: false,
                 };
 
-                chunk_metadata
+                leases.insert(chunk_handle, lease.clone());
+
+                lease
             }
-            None => {
-                //Error, file not created, so it is missing in lookup table
-                todo!()
+        }
+    }
+
+    // Stops extending lease of given primary. Record is kept until lease expires,
+    // because primary may still hold it and master can't grant it to other replica before.
+    pub fn revoke_lease(&self, chunk_handle: u64, primary: &str) {
+        let mut leases = self.leases.lock().unwrap();
+
+        if let Some(lease) = leases
+            .get_mut(&chunk_handle)
+            .filter(|lease| lease.primary == primary)
+        {
+            lease.revoked = true;
+        }
+    }
+
+    // Extends valid leases held by given server, returns handles of extended ones.
+    // Expired lease is not extended, because master could already grant it to other replica.
+    // Revoked lease is not extended either, so it expires and chunk gets new primary.
+    pub fn extend_leases(
+        &self,
+        server_address: &str,
+        chunk_handles: &[u64],
+        expiration: SystemTime,
+    ) -> Vec<u64> {
+        let mut leases = self.leases.lock().unwrap();
+
+        chunk_handles
+            .iter()
+            .filter(|chunk_handle| match leases.get_mut(chunk_handle) {
+                Some(lease)
+                    if lease.primary == server_address && lease.is_valid() && !lease.revoked =>
+                {
+                    lease.expiration = expiration;
+                    true
+                }
+                _ => false,
+            })
+            .copied()
+            .collect()
+    }
+
+    // Applies operation to in-memory state and appends it to operation log.
+    // Returns after operation is persisted, operations that failed are not logged.
+    // Caller holds path locks, so conflicting operation can not be applied in between.
+    fn commit(&self, operation: Operation) -> Result<(), Error> {
+        let _mutations = self.checkpoint_lock.read().unwrap();
+
+        self.apply(&operation)?;
+
+        append_or_abort(&mut self.operation_log.lock().unwrap(), &operation);
+
+        Ok(())
+    }
+
+    // Used both by new mutations and during log replay
+    fn apply(&self, operation: &Operation) -> Result<(), Error> {
+        match operation {
+            Operation::Mkdir { path } => {
+                let dropped = self.namespace.lock().unwrap().mkdir(path)?;
+
+                self.drop_files(dropped);
+            }
+            Operation::CreateFile {
+                file_path,
+                created_at,
+            } => {
+                let dropped = self
+                    .namespace
+                    .lock()
+                    .unwrap()
+                    .create_file(file_path, *created_at)?;
+
+                self.drop_files(dropped);
+
+                self.filepath_to_chunk_handles
+                    .lock()
+                    .unwrap()
+                    .insert(file_path.to_string(), BTreeMap::new());
+            }
+            Operation::DeleteFile {
+                file_path,
+                deleted_at,
+            } => {
+                // Chunk mapping is kept, so file can be restored until it is purged
+                self.namespace
+                    .lock()
+                    .unwrap()
+                    .delete_file(file_path, *deleted_at)?;
+            }
+            Operation::DeleteDirectory {
+                path,
+                deleted_at,
+                recursive,
+            } => {
+                // Files are marked as deleted, chunk mapping is kept until they are purged
+                self.namespace
+                    .lock()
+                    .unwrap()
+                    .delete_directory(path, *deleted_at, *recursive)?;
+            }
+            Operation::Rename {
+                src,
+                dst,
+                overwrite,
+            } => {
+                let dropped = self
+                    .namespace
+                    .lock()
+                    .unwrap()
+                    .rename(src, dst, *overwrite)?;
+
+                // Replaced files are dropped first, moved files take their paths
+                self.drop_files(dropped);
+                self.move_files(src, dst);
+            }
+            Operation::RestoreFile { file_path } => {
+                self.namespace.lock().unwrap().restore_file(file_path)?;
+            }
+            Operation::PurgeFile {
+                file_path,
+                deleted_at,
+            } => {
+                self.namespace
+                    .lock()
+                    .unwrap()
+                    .purge_file(file_path, *deleted_at)?;
+
+                self.drop_files(vec![file_path.to_string()]);
+            }
+            Operation::AllocateChunk {
+                file_path,
+                chunk_index,
+                chunk_handle,
+            } => {
+                // Update lookup table
+                match self
+                    .filepath_to_chunk_handles
+                    .lock()
+                    .unwrap()
+                    .get_mut(file_path)
+                {
+                    Some(handles) => {
+                        if handles.contains_key(chunk_index) {
+                            return Err(Error::AlreadyExists(file_path.to_string()));
+                        }
+
+                        handles.insert(*chunk_index, *chunk_handle);
+
+                        self.chunk_handle_to_file
+                            .lock()
+                            .unwrap()
+                            .insert(*chunk_handle, file_path.to_string());
+                    }
+                    None => {
+                        // File not created, so it is missing in lookup table
+                        return Err(Error::NotFound(file_path.to_string()));
+                    }
+                }
+
+                // Replayed allocation moves counter past handles given before restart
+                let mut next_chunk_handle = self.next_chunk_handle.lock().unwrap();
+                *next_chunk_handle = (*next_chunk_handle).max(chunk_handle.saturating_add(1));
+            }
+            Operation::BumpChunkVersion {
+                chunk_handle,
+                version,
+            } => {
+                // Bump is not ordered with purge of its file by path locks. Chunk that is
+                // already forgotten is skipped, so replay ends with the same versions.
+                let owners = self.chunk_handle_to_file.lock().unwrap();
+
+                if owners.contains_key(chunk_handle) {
+                    let mut versions = self.chunk_versions.lock().unwrap();
+                    let current = versions.entry(*chunk_handle).or_default();
+
+                    *current = match version {
+                        0 => *current + 1,
+                        version => (*current).max(*version),
+                    };
+                }
             }
         }
+
+        Ok(())
     }
 
-    fn generate_chunk_handle(&self, file_path: &str, chunk_id: u64) -> u64 {
-        let user_id = 1;
+    // Drops chunk mapping of purged or replaced files with their chunks
+    fn drop_files(&self, file_paths: Vec<String>) {
+        let mut chunk_handles = Vec::new();
 
-        let mut hasher = DefaultHasher::new();
-        user_id.hash(&mut hasher);
-        file_path.hash(&mut hasher);
-        chunk_id.hash(&mut hasher);
+        {
+            let mut files = self.filepath_to_chunk_handles.lock().unwrap();
+
+            for file_path in file_paths {
+                if let Some(handles) = files.remove(&file_path) {
+                    chunk_handles.extend(handles.into_values());
+                }
+            }
+        }
 
-        hasher.finish()
+        self.forget_chunks(chunk_handles);
+    }
+
+    // Chunk mapping of files under moved path follows them
+    fn move_files(&self, src: &str, dst: &str) {
+        let mut files = self.filepath_to_chunk_handles.lock().unwrap();
+        let mut owners = self.chunk_handle_to_file.lock().unwrap();
+
+        let prefix = format!("{}/", src);
+        let moved: Vec<String> = files
+            .keys()
+            .filter(|file_path| *file_path == src || file_path.starts_with(&prefix))
+            .cloned()
+            .collect();
+
+        for file_path in moved {
+            if let Some(handles) = files.remove(&file_path) {
+                let new_path = format!("{}{}", dst, &file_path[src.len()..]);
+
+                for chunk_handle in handles.values() {
+                    owners.insert(*chunk_handle, new_path.clone());
+                }
+
+                files.insert(new_path, handles);
+            }
+        }
+    }
+
+    // Drops everything known about chunks that no longer belong to any file,
+    // their replicas are deleted by chunk servers after next heartbeat
+    fn forget_chunks(&self, chunk_handles: impl IntoIterator<Item = u64>) {
+        let mut owners = self.chunk_handle_to_file.lock().unwrap();
+        let mut locations_map = self.chunk_handle_to_chunk_servers.lock().unwrap();
+        let mut leases = self.leases.lock().unwrap();
+
+        let mut versions = self.chunk_versions.lock().unwrap();
+        let mut sizes = self.chunk_sizes.lock().unwrap();
+
+        for chunk_handle in chunk_handles {
+            owners.remove(&chunk_handle);
+            locations_map.remove(&chunk_handle);
+            leases.remove(&chunk_handle);
+            versions.remove(&chunk_handle);
+            sizes.remove(&chunk_handle);
+        }
+    }
+
+    // Handle of failed allocation is skipped, so handles only grow but can have gaps
+    fn generate_chunk_handle(&self) -> u64 {
+        let mut next_chunk_handle = self.next_chunk_handle.lock().unwrap();
+
+        let chunk_handle = *next_chunk_handle;
+        *next_chunk_handle += 1;
+
+        chunk_handle
     }
 
     fn get_locations_for_chunk(&self) -> Vec<String> {
-        // For now will take 3 servers with greatest available space
+        // For now will take servers with greatest available space
         let servers = self.chunk_servers.lock().unwrap();
 
-        let mut entries: Vec<_> = servers.iter().collect();
-        entries.sort_by(|a, b| b.1.available.cmp(&a.1.available));
+        // Suspect and dead servers are skipped
+        let mut entries: Vec<_> = servers
+            .iter()
+            .filter(|(_, status)| status.state == ChunkServerState::Alive)
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.1.available));
 
-        // Map the top three entries (if available) to their addresses
-        let servers = entries
+        // Map the top entries (if available) to their addresses
+        entries
             .iter()
-            .take(3) // Take only the top three entries
+            .take(REPLICATION_FACTOR)
             .map(|(_key, status)| status.address.clone())
+            .collect()
+    }
+
+    // Marks servers without recent heartbeat as suspect or dead and returns newly dead ones.
+    // Replicas of dead servers are dropped, so chunks get re-replicated. Their leases are
+    // kept until they expire, dead server is not heartbeating so they are not extended.
+    pub fn detect_dead_servers(
+        &self,
+        suspect_timeout: Duration,
+        dead_timeout: Duration,
+    ) -> Vec<String> {
+        let mut servers = self.chunk_servers.lock().unwrap();
+
+        let mut dead = Vec::new();
+
+        for status in servers.values_mut() {
+            let elapsed = status.last_heartbeat.elapsed();
+
+            let state = if elapsed >= dead_timeout {
+                ChunkServerState::Dead
+            } else if elapsed >= suspect_timeout {
+                ChunkServerState::Suspect
+            } else {
+                ChunkServerState::Alive
+            };
+
+            if state != status.state {
+                warn!(
+                    "Chunk server: {} is {:?}, last heartbeat: {:?} ago",
+                    status.address, state, elapsed
+                );
+
+                if state == ChunkServerState::Dead {
+                    dead.push(status.address.clone());
+                }
+
+                status.state = state;
+            }
+        }
+
+        drop(servers);
+
+        if dead.is_empty() {
+            return dead;
+        }
+
+        for locations in self
+            .chunk_handle_to_chunk_servers
+            .lock()
+            .unwrap()
+            .values_mut()
+        {
+            locations.retain(|address| !dead.contains(address));
+        }
+
+        dead
+    }
+
+    pub fn list_chunk_servers(&self) -> Vec<ChunkServerInfo> {
+        let servers = self.chunk_servers.lock().unwrap();
+
+        let mut servers_info: Vec<ChunkServerInfo> = servers
+            .values()
+            .map(|status| ChunkServerInfo {
+                address: status.address.clone(),
+                state: status.state.into(),
+                used: status.used,
+                available: status.available,
+                chunk_count: status.chunk_handles.len() as u64,
+                last_heartbeat: status.last_heartbeat.elapsed().as_secs(),
+            })
             .collect();
 
-        servers
+        servers_info.sort_by(|a, b| a.address.cmp(&b.address));
+
+        servers_info
     }
 
-    pub fn heartbeat_update(&self, request: HeartbeatRequest) -> Vec<String> {
+    pub fn heartbeat_update(&self, request: HeartbeatRequest) -> Vec<u64> {
         // This also acts as chunk server registration
 
+        let chunk_server_handles: HashSet<u64> = request
+            .chunks
+            .iter()
+            .map(|chunk| chunk.chunk_handle)
+            .collect();
+
+        // Chunks that do not belong to any file are deleted by chunk server
+        let mut to_delete = self.get_outdated_chunks(&chunk_server_handles);
+
+        // Stale replica is deleted only when replica with current version is known,
+        // otherwise it is kept but not used until up-to-date replica reports chunk
+        let has_other_replica: HashSet<u64> = {
+            let locations_map = self.chunk_handle_to_chunk_servers.lock().unwrap();
+
+            chunk_server_handles
+                .iter()
+                .filter(|chunk_handle| {
+                    locations_map.get(*chunk_handle).is_some_and(|locations| {
+                        locations
+                            .iter()
+                            .any(|address| *address != request.server_address)
+                    })
+                })
+                .copied()
+                .collect()
+        };
+        let mut kept_stale = HashSet::new();
+
+        // Replica that missed version update while server was down has old data
+        let versions = self.chunk_versions.lock().unwrap();
+
+        // Sizes of replicas with current version
+        let mut reported_sizes = Vec::new();
+
+        // Versions that replicas got before master failed to log them
+        let mut newer_versions = Vec::new();
+
+        for StoredChunk {
+            chunk_handle,
+            version,
+            size,
+            modified_at,
+        } in request.chunks.iter()
+        {
+            if to_delete.contains(chunk_handle) {
+                continue;
+            }
+
+            let current = versions.get(chunk_handle).copied().unwrap_or(0);
+
+            if *version < current {
+                warn!(
+                    "Chunk: {} on: {} is stale, version: {}, current: {}",
+                    chunk_handle, request.server_address, version, current
+                );
+
+                if has_other_replica.contains(chunk_handle) {
+                    to_delete.insert(*chunk_handle);
+                } else {
+                    kept_stale.insert(*chunk_handle);
+                }
+                continue;
+            }
+
+            if *version > current {
+                // Master failed before version was logged, replica is up to date
+                warn!(
+                    "Chunk: {} on: {} has newer version: {}, current: {}",
+                    chunk_handle, request.server_address, version, current
+                );
+                newer_versions.push((*chunk_handle, *version));
+            }
+
+            reported_sizes.push((
+                *chunk_handle,
+                ChunkSize {
+                    size: *size,
+                    modified_at: *modified_at,
+                },
+            ));
+        }
+
+        drop(versions);
+
+        // Newer version is logged, so it is not lost on restart and replicas that
+        // don't have it are still recognized as stale
+        for (chunk_handle, version) in newer_versions {
+            if let Err(e) = self.bump_chunk_version(chunk_handle, version) {
+                warn!(
+                    "Failed to record version: {} of chunk: {}, because: {}",
+                    version, chunk_handle, e
+                );
+            }
+        }
+
+        // Heartbeat only fills sizes missed by master, e.g. after restart.
+        // Time of write known to master is kept, clone of chunk is newer file with the same data.
+        let mut sizes = self.chunk_sizes.lock().unwrap();
+
+        for (handle, reported) in reported_sizes {
+            match sizes.get_mut(&handle) {
+                Some(chunk) if reported.size > chunk.size => {
+                    chunk.size = reported.size;
+                    chunk.modified_at = chunk.modified_at.max(reported.modified_at);
+                }
+                Some(_) => {}
+                None => {
+                    sizes.insert(handle, reported);
+                }
+            }
+        }
+
+        drop(sizes);
+
         let mut servers = self.chunk_servers.lock().unwrap();
-        let chunk_server_handles: HashSet<String> = request.chunk_handles.iter().cloned().collect();
 
         // Update server status map
         match servers.get_mut(&request.server_address) {
@@ -172,28 +1215,74 @@ impl Metadata {
                 status.available = request.available;
                 status.used = request.used;
                 status.last_heartbeat = Instant::now();
+
+                if status.state != ChunkServerState::Alive {
+                    info!(
+                        "Chunk server: {} is alive again, was: {:?}",
+                        status.address, status.state
+                    );
+                    status.state = ChunkServerState::Alive;
+                }
+
                 // Save state, will be updated to correct values in next heartbeat
                 // Do i even need this ?
                 status.chunk_handles = chunk_server_handles.clone();
             }
             None => {
                 // Registration
-                let server_status = ChunkServerStatus {
-                    address: request.server_address.clone(),
-                    used: request.used,
-                    available: request.available,
-                    chunk_handles: chunk_server_handles.clone(),
-                    last_heartbeat: Instant::now(),
-                };
+                let server_status = ChunkServerStatus::new(
+                    request.server_address.clone(),
+                    request.used,
+                    request.available,
+                    chunk_server_handles.clone(),
+                );
 
                 servers.insert(request.server_address.clone(), server_status);
             }
         }
 
+        drop(servers);
+
         let mut locations_map = self.chunk_handle_to_chunk_servers.lock().unwrap();
 
+        // Corrupted replica is dropped, so chunk is under-replicated and cloned from healthy one.
+        // Last copy is kept but not used, like stale one, its other blocks may be still readable.
+        for handle in request.corrupted_chunks.iter() {
+            warn!(
+                "Chunk: {} is corrupted on: {}",
+                handle, request.server_address
+            );
+
+            let has_other_replica = match locations_map.get_mut(handle) {
+                Some(locations_set) => {
+                    locations_set.remove(&request.server_address);
+                    !locations_set.is_empty()
+                }
+                None => false,
+            };
+
+            self.revoke_lease(*handle, &request.server_address);
+
+            if has_other_replica {
+                to_delete.insert(*handle);
+            } else {
+                warn!(
+                    "Chunk: {} has no other replica, corrupted copy on: {} is kept",
+                    handle, request.server_address
+                );
+            }
+        }
+
         // Update chunk_handle to locations map
         for handle in chunk_server_handles.iter() {
+            if to_delete.contains(handle) || kept_stale.contains(handle) {
+                if let Some(locations_set) = locations_map.get_mut(handle) {
+                    locations_set.remove(&request.server_address);
+                }
+
+                continue;
+            }
+
             match locations_map.get_mut(handle) {
                 Some(locations_set) => {
                     locations_set.insert(request.server_address.clone());
@@ -202,45 +1291,37 @@ impl Metadata {
                     // If handle not presend here it means that it was allocated and upload was finished
                     let mut new_set = HashSet::new();
                     new_set.insert(request.server_address.clone());
-                    locations_map.insert(handle.to_string(), new_set);
+                    locations_map.insert(*handle, new_set);
                 }
             }
         }
 
-        // do not have corresponding file or file marked as to_delete
-        let to_delete = self.get_outdated_chunks(&chunk_server_handles);
-
-        to_delete
+        to_delete.into_iter().collect()
     }
 
-    fn get_outdated_chunks(&self, set_to_verify: &HashSet<String>) -> Vec<String> {
-        // Check file 2 chunks map if file present
-
-        let map = self.filepath_to_chunk_handles.lock().unwrap();
-
-        let mut to_delete = Vec::new();
+    fn get_outdated_chunks(&self, set_to_verify: &HashSet<u64>) -> HashSet<u64> {
+        // Chunks of deleted files are kept until file is purged, so they can be restored
+        let owners = self.chunk_handle_to_file.lock().unwrap();
 
-        for handle in set_to_verify {
-            match map.iter().find(|(file_path, file_chunks)| {
-                if file_chunks.contains(&handle.parse().unwrap()) {
-                    return true;
-                }
-                false
-            }) {
-                Some((file_path, _)) => {
-                    if !self.namespace.lock().unwrap().is_active(file_path) {
-                        to_delete.push(handle.to_owned());
-                    }
-                }
-                None => {
-                    // File deleted, not present in file_path to chunks map
-                    // File should be deleted after chunks have been deleted, so this scenario is
-                    // possible if chunk server was not operational but get back from the dead and has stale chunks
-                    to_delete.push(handle.to_owned());
-                }
-            }
-        }
+        // Chunk is not owned when its file was purged or replaced, or when chunk server
+        // was not operational during purge and came back from the dead with stale chunks
+        set_to_verify
+            .iter()
+            .filter(|chunk_handle| !owners.contains_key(*chunk_handle))
+            .copied()
+            .collect()
+    }
+}
 
-        to_delete
+// Operation is validated by applying it, so when it can't be persisted memory already
+// differs from log. Master stops instead of serving state that is lost on restart,
+// after restart it recovers state from checkpoint and log.
+fn append_or_abort(operation_log: &mut OperationLog, operation: &Operation) {
+    if let Err(e) = operation_log.append(operation) {
+        error!(
+            "Failed to append operation: {:?} to operation log: {}",
+            operation, e
+        );
+        std::process::abort();
     }
 }
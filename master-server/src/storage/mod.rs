@@ -1,27 +1,122 @@
+mod checkpoint;
+mod locks;
 pub mod metadata;
 mod namespace;
 mod operation_log;
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
+    use std::{
+        collections::HashSet,
+        env, fs,
+        io::Write,
+        path::{Path, PathBuf},
+        sync::{
+            mpsc::{self, RecvTimeoutError},
+            Arc,
+        },
+        thread,
+        time::{Duration, SystemTime},
+    };
 
+    use crate::error::Error;
+    use common::{
+        master_server::{ChunkMetadata, ChunkServerState, HeartbeatRequest, StoredChunk},
+        path::DfsPath,
+    };
     use tests::{
-        metadata::{ChunkServerStatus, Metadata},
+        locks::{LockMode, PathLocks},
+        metadata::{ChunkServerStatus, CloneTask, Metadata},
         namespace::{Namespace, Node, Status},
     };
+    use uuid::Uuid;
 
     use super::*;
 
+    fn test_data_path() -> PathBuf {
+        let path = env::temp_dir().join(format!("master-server-test-{}", Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        path
+    }
+
+    fn assert_same_metadata(left: &Metadata, right: &Metadata) {
+        assert_eq!(
+            *left.namespace.lock().unwrap(),
+            *right.namespace.lock().unwrap()
+        );
+        assert_eq!(
+            *left.filepath_to_chunk_handles.lock().unwrap(),
+            *right.filepath_to_chunk_handles.lock().unwrap()
+        );
+        assert_eq!(
+            *left.chunk_versions.lock().unwrap(),
+            *right.chunk_versions.lock().unwrap()
+        );
+        assert_eq!(
+            *left.next_chunk_handle.lock().unwrap(),
+            *right.next_chunk_handle.lock().unwrap()
+        );
+        assert_eq!(
+            *left.chunk_handle_to_file.lock().unwrap(),
+            *right.chunk_handle_to_file.lock().unwrap()
+        );
+    }
+
+    fn stored_chunks(chunk_handles: &[u64], version: u64) -> Vec<StoredChunk> {
+        chunk_handles
+            .iter()
+            .map(|chunk_handle| StoredChunk {
+                chunk_handle: *chunk_handle,
+                version,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    // Chunks can be allocated only for visible file, so deleted file is restored for it
+    fn allocate_chunk_of_deleted_file(
+        metadata: &Metadata,
+        file_path: &str,
+        chunk_index: u64,
+    ) -> ChunkMetadata {
+        metadata.restore_file(file_path.to_string()).unwrap();
+        let chunk_metadata = metadata.allocate_chunk(file_path, chunk_index).unwrap();
+        metadata.delete_file(file_path.to_string()).unwrap();
+
+        chunk_metadata
+    }
+
+    fn populate(metadata: &Metadata) {
+        let server = ChunkServerStatus::new("123".to_string(), 0, 1000000, HashSet::new());
+        metadata
+            .chunk_servers
+            .lock()
+            .unwrap()
+            .insert(server.address.clone(), server);
+
+        metadata.mkdir("/path/to/new/directory").unwrap();
+        metadata
+            .create_file("/path/to/new/file".to_string())
+            .unwrap();
+        metadata
+            .create_file("/path/to/deleted_file".to_string())
+            .unwrap();
+        metadata.allocate_chunk("/path/to/new/file", 1).unwrap();
+        metadata.allocate_chunk("/path/to/new/file", 2).unwrap();
+        metadata
+            .delete_file("/path/to/deleted_file".to_string())
+            .unwrap();
+    }
+
     #[test]
     fn mkdir_should_create_dir() {
         let mut namespace = Namespace::new();
-        namespace.mkdir("/path/to/new/directory");
+        namespace.mkdir("/path/to/new/directory").unwrap();
 
-        let path_dir = namespace.ls("/path");
-        let to_dir = namespace.ls("/path/to");
-        let new_dir = namespace.ls("/path/to/new");
-        let directory_dir = namespace.ls("/path/to/new/directory");
+        let path_dir = namespace.ls("/path").unwrap();
+        let to_dir = namespace.ls("/path/to").unwrap();
+        let new_dir = namespace.ls("/path/to/new").unwrap();
+        let directory_dir = namespace.ls("/path/to/new/directory").unwrap();
 
         assert_eq!(path_dir.len(), 1);
         assert_eq!(path_dir[0], "to");
@@ -35,13 +130,15 @@ mod tests {
     #[test]
     fn crate_file_should_create_file() {
         let mut namespace = Namespace::new();
-        namespace.mkdir("/path/to");
-        namespace.create_file("/path/to/new/directory/new_file");
+        namespace.mkdir("/path/to").unwrap();
+        namespace
+            .create_file("/path/to/new/directory/new_file", 0)
+            .unwrap();
 
-        let path_dir = namespace.ls("/path");
-        let to_dir = namespace.ls("/path/to");
-        let new_dir = namespace.ls("/path/to/new");
-        let directory_dir = namespace.ls("/path/to/new/directory");
+        let path_dir = namespace.ls("/path").unwrap();
+        let to_dir = namespace.ls("/path/to").unwrap();
+        let new_dir = namespace.ls("/path/to/new").unwrap();
+        let directory_dir = namespace.ls("/path/to/new/directory").unwrap();
 
         assert_eq!(path_dir.len(), 1);
         assert_eq!(path_dir[0], "to");
@@ -56,31 +153,31 @@ mod tests {
     #[test]
     fn delete_file_should_mark_file_as_deleted() {
         let mut namespace = Namespace::new();
-        namespace.create_file("/dir/new_file");
+        namespace.create_file("/dir/new_file", 0).unwrap();
 
-        let path_dir = namespace.ls("/dir");
+        let path_dir = namespace.ls("/dir").unwrap();
 
         assert_eq!(path_dir.len(), 1);
         assert_eq!(path_dir[0], "new_file");
 
-        namespace.delete_file("/dir/new_file");
+        namespace.delete_file("/dir/new_file", 1000).unwrap();
 
-        let path_dir = namespace.ls("/dir");
+        let path_dir = namespace.ls("/dir").unwrap();
         assert_eq!(path_dir.len(), 0);
 
-        match namespace.root.get_node("dir").get_node("new_file") {
-            Node::Directory { name, nodes } => {
+        match namespace.get_node_mut("/dir/new_file").unwrap() {
+            Node::Directory { .. } => {
                 panic!("Should be file not directort");
             }
-            Node::File { name, status } => {
-                assert_eq!(Status::Deleted, *status)
+            Node::File { status, .. } => {
+                assert_eq!(Status::Deleted { deleted_at: 1000 }, *status)
             }
         }
     }
 
     #[test]
     fn allocate_chunk_should_update_lookup_table() {
-        let metadata = Metadata::new();
+        let metadata = Metadata::recover(&test_data_path()).unwrap();
         let mut servers = metadata.chunk_servers.lock().unwrap();
 
         let server1 = ChunkServerStatus::new("123".to_string(), 1000000, 1000000, HashSet::new());
@@ -97,9 +194,1380 @@ mod tests {
 
         let file_path = "/test/directory/test_file.txt";
 
-        metadata.create_file(file_path.to_string());
-        let chunk_metadata = metadata.allocate_chunk(file_path, 1);
+        metadata.create_file(file_path.to_string()).unwrap();
+        let chunk_metadata = metadata.allocate_chunk(file_path, 1).unwrap();
 
         assert_eq!(chunk_metadata.locations.len(), 3);
     }
+
+    #[test]
+    fn open_file_should_return_chunks_ordered_by_index() {
+        let metadata = Metadata::recover(&test_data_path()).unwrap();
+        let server = ChunkServerStatus::new("123".to_string(), 0, 1000000, HashSet::new());
+        metadata
+            .chunk_servers
+            .lock()
+            .unwrap()
+            .insert(server.address.clone(), server);
+
+        let file_path = "/test/file";
+        metadata.create_file(file_path.to_string()).unwrap();
+
+        let second = metadata.allocate_chunk(file_path, 1).unwrap();
+        let third = metadata.allocate_chunk(file_path, 2).unwrap();
+        let first = metadata.allocate_chunk(file_path, 0).unwrap();
+
+        let chunks = metadata.open_file(file_path).unwrap();
+
+        let handles: Vec<u64> = chunks.iter().map(|chunk| chunk.chunk_handle).collect();
+        assert_eq!(
+            handles,
+            vec![first.chunk_handle, second.chunk_handle, third.chunk_handle]
+        );
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.locations == vec!["123".to_string()]));
+
+        let indexes: Vec<u64> = chunks.iter().map(|chunk| chunk.chunk_index).collect();
+        assert_eq!(indexes, vec![0, 1, 2]);
+        assert_eq!(second.chunk_index, 1);
+
+        // File can have gaps, chunks are returned with their indexes
+        metadata.allocate_chunk(file_path, 5).unwrap();
+        let last = metadata.open_file(file_path).unwrap().pop().unwrap();
+        assert_eq!(last.chunk_index, 5);
+
+        assert!(metadata.open_file("/test/missing").is_err());
+    }
+
+    #[test]
+    fn namespace_should_return_errors_for_invalid_requests() {
+        let mut namespace = Namespace::new();
+        namespace.mkdir("/dir").unwrap();
+        namespace.create_file("/dir/file", 0).unwrap();
+
+        assert!(matches!(namespace.ls("/missing"), Err(Error::NotFound(_))));
+        assert!(matches!(
+            namespace.ls("/dir/file"),
+            Err(Error::NotADirectory(_))
+        ));
+        assert!(matches!(
+            namespace.create_file("/dir/file", 0),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            namespace.create_file("/dir/file/nested", 0),
+            Err(Error::NotADirectory(_))
+        ));
+        assert!(matches!(
+            namespace.mkdir("/dir/file"),
+            Err(Error::NotADirectory(_))
+        ));
+        assert!(matches!(
+            namespace.delete_file("/dir", 0),
+            Err(Error::IsADirectory(_))
+        ));
+        assert!(matches!(
+            namespace.purge_file("/dir/file", 0),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            namespace.ls("dir"),
+            Err(Error::InvalidPath { .. })
+        ));
+        assert!(matches!(
+            namespace.ls("/dir/../dir"),
+            Err(Error::InvalidPath { .. })
+        ));
+
+        namespace.delete_file("/dir/file", 0).unwrap();
+        assert!(matches!(
+            namespace.delete_file("/dir/file", 0),
+            Err(Error::NotFound(_))
+        ));
+
+        // Name of deleted file can be reused
+        namespace.create_file("/dir/file", 0).unwrap();
+        assert_eq!(namespace.ls("/").unwrap(), vec!["dir"]);
+    }
+
+    #[test]
+    fn paths_should_be_normalized_before_use() {
+        let data_path = test_data_path();
+
+        {
+            let metadata = Metadata::recover(&data_path).unwrap();
+            populate(&metadata);
+
+            metadata.create_file("//dir//file/".to_string()).unwrap();
+
+            assert!(matches!(
+                metadata.create_file("/dir/file".to_string()),
+                Err(Error::AlreadyExists(_))
+            ));
+            assert_eq!(metadata.ls("/dir/").unwrap(), vec!["file"]);
+            assert!(metadata.open_file("/dir//file").unwrap().is_empty());
+            assert!(metadata.allocate_chunk("/dir/file/", 0).is_ok());
+            assert!(matches!(
+                metadata.mkdir("relative/dir"),
+                Err(Error::InvalidPath { .. })
+            ));
+        }
+
+        // Normalized path is logged
+        let metadata = Metadata::recover(&data_path).unwrap();
+
+        assert_eq!(metadata.open_file("/dir/file").unwrap().len(), 1);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn lease_should_have_single_primary_until_it_expires() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunk_handle = metadata.open_file("/path/to/new/file").unwrap()[0].chunk_handle;
+        let expiration = SystemTime::now() + Duration::from_secs(60);
+
+        assert_eq!(metadata.get_lease(chunk_handle), None);
+
+        let lease = metadata.acquire_lease(chunk_handle, "123", expiration);
+
+        // Concurrent grant gets already recorded lease
+        assert_eq!(
+            metadata.acquire_lease(chunk_handle, "456", expiration),
+            lease
+        );
+        assert_eq!(
+            metadata.open_file("/path/to/new/file").unwrap()[0].primary,
+            "123"
+        );
+
+        // Only primary can extend lease
+        let handles = vec![chunk_handle];
+        let extended = expiration + Duration::from_secs(60);
+
+        assert!(metadata.extend_leases("456", &handles, extended).is_empty());
+        assert_eq!(metadata.extend_leases("123", &handles, extended), handles);
+        assert_eq!(
+            metadata.get_lease(chunk_handle).unwrap().expiration,
+            extended
+        );
+
+        // Revoked lease is kept until it expires, but is not extended
+        metadata.revoke_lease(chunk_handle, "123");
+
+        assert_eq!(metadata.get_lease(chunk_handle).unwrap().primary, "123");
+        assert!(metadata.extend_leases("123", &handles, extended).is_empty());
+        assert_eq!(
+            metadata
+                .acquire_lease(chunk_handle, "456", expiration)
+                .primary,
+            "123"
+        );
+
+        // Expired lease can be granted to other replica
+        let chunk_handle = metadata.open_file("/path/to/new/file").unwrap()[1].chunk_handle;
+        let handles = vec![chunk_handle];
+        metadata.acquire_lease(chunk_handle, "123", SystemTime::now());
+
+        assert_eq!(metadata.get_lease(chunk_handle), None);
+        assert!(metadata.extend_leases("123", &handles, extended).is_empty());
+        assert_eq!(
+            metadata
+                .acquire_lease(chunk_handle, "456", expiration)
+                .primary,
+            "456"
+        );
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn allocate_chunk_should_fail_without_file_or_servers() {
+        let metadata = Metadata::recover(&test_data_path()).unwrap();
+        metadata.create_file("/file".to_string()).unwrap();
+
+        assert!(matches!(
+            metadata.allocate_chunk("/missing", 0),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            metadata.allocate_chunk("/file", 0),
+            Err(Error::NoChunkServers)
+        ));
+    }
+
+    #[test]
+    fn allocated_chunk_should_be_returned_again_for_same_index() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunks = metadata.open_file("/path/to/new/file").unwrap();
+        let allocated = metadata.allocate_chunk("/path/to/new/file", 1).unwrap();
+
+        assert_eq!(allocated.chunk_handle, chunks[0].chunk_handle);
+        assert_eq!(metadata.open_file("/path/to/new/file").unwrap(), chunks);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn chunks_with_fewest_replicas_should_be_cloned_first() {
+        let metadata = Metadata::recover(&test_data_path()).unwrap();
+
+        let register = |address: &str, available: u64| {
+            let server = ChunkServerStatus::new(address.to_string(), 0, available, HashSet::new());
+            metadata
+                .chunk_servers
+                .lock()
+                .unwrap()
+                .insert(address.to_string(), server);
+        };
+
+        register("a", 400);
+        register("b", 300);
+        register("c", 200);
+        register("d", 100);
+
+        metadata.create_file("/file".to_string()).unwrap();
+        let first = metadata.allocate_chunk("/file", 0).unwrap().chunk_handle;
+        let second = metadata.allocate_chunk("/file", 1).unwrap().chunk_handle;
+
+        assert!(metadata.plan_replication(10).is_empty());
+
+        // First chunk is left only on "a", second on "a" and "d"
+        metadata.chunk_servers.lock().unwrap().remove("b");
+        metadata.chunk_servers.lock().unwrap().remove("c");
+        metadata.add_chunk_location(second, "d", 0);
+        register("e", 50);
+
+        let clone = |chunk_handle: u64, source: &str, target: &str| CloneTask {
+            chunk_handle,
+            version: 0,
+            source: source.to_string(),
+            target: target.to_string(),
+        };
+
+        assert_eq!(
+            metadata.plan_replication(2),
+            vec![clone(first, "a", "d"), clone(first, "a", "e")]
+        );
+        assert_eq!(
+            metadata.plan_replication(10),
+            vec![
+                clone(first, "a", "d"),
+                clone(first, "a", "e"),
+                clone(second, "a", "e")
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_server_should_be_dropped_from_placement_and_locations() {
+        let metadata = Metadata::recover(&test_data_path()).unwrap();
+        populate(&metadata);
+
+        let state = |metadata: &Metadata| metadata.list_chunk_servers()[0].state;
+
+        assert_eq!(
+            metadata.detect_dead_servers(Duration::ZERO, Duration::from_secs(60)),
+            Vec::<String>::new()
+        );
+        assert_eq!(state(&metadata), ChunkServerState::Suspect as i32);
+
+        // Suspect server keeps its replicas, but does not get new chunks
+        assert_eq!(
+            metadata.open_file("/path/to/new/file").unwrap()[0].locations,
+            vec!["123"]
+        );
+        metadata.create_file("/other".to_string()).unwrap();
+        assert!(matches!(
+            metadata.allocate_chunk("/other", 0),
+            Err(Error::NoChunkServers)
+        ));
+
+        assert_eq!(
+            metadata.detect_dead_servers(Duration::ZERO, Duration::ZERO),
+            vec!["123"]
+        );
+        assert_eq!(state(&metadata), ChunkServerState::Dead as i32);
+        assert!(metadata.open_file("/path/to/new/file").unwrap()[0]
+            .locations
+            .is_empty());
+
+        // Heartbeat brings server back with replicas it reports
+        let chunk_handle = metadata.open_file("/path/to/new/file").unwrap()[0].chunk_handle;
+        metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: stored_chunks(&[chunk_handle], 0),
+            ..Default::default()
+        });
+
+        assert_eq!(state(&metadata), ChunkServerState::Alive as i32);
+        assert_eq!(
+            metadata.open_file("/path/to/new/file").unwrap()[0].locations,
+            vec!["123"]
+        );
+    }
+
+    #[test]
+    fn deleted_file_should_be_purged_after_grace_period() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        // Deleted file can't get new chunks
+        assert!(matches!(
+            metadata.allocate_chunk("/path/to/deleted_file", 0),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            metadata.open_file("/path/to/deleted_file"),
+            Err(Error::NotFound(_))
+        ));
+
+        let chunk_handle =
+            allocate_chunk_of_deleted_file(&metadata, "/path/to/deleted_file", 0).chunk_handle;
+        let orphaned = 999_999;
+
+        let heartbeat = |metadata: &Metadata| {
+            metadata.heartbeat_update(HeartbeatRequest {
+                server_address: "123".to_string(),
+                chunks: stored_chunks(&[chunk_handle, orphaned], 0),
+                ..Default::default()
+            })
+        };
+
+        // Chunks of deleted file are kept during grace period
+        assert!(metadata
+            .collect_garbage(Duration::from_secs(60))
+            .unwrap()
+            .is_empty());
+        assert_eq!(heartbeat(&metadata), vec![orphaned]);
+
+        assert_eq!(
+            metadata.collect_garbage(Duration::ZERO).unwrap(),
+            vec!["/path/to/deleted_file"]
+        );
+
+        let mut to_delete = heartbeat(&metadata);
+        to_delete.sort();
+
+        assert_eq!(to_delete, vec![chunk_handle, orphaned]);
+        assert!(matches!(
+            metadata.create_file("/path/to/deleted_file".to_string()),
+            Ok(())
+        ));
+
+        // Purge is logged
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn deleted_file_should_be_restored_until_it_is_purged() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        metadata
+            .create_file("/path/to/restored_file".to_string())
+            .unwrap();
+        let chunk_metadata = metadata
+            .allocate_chunk("/path/to/restored_file", 0)
+            .unwrap();
+        metadata
+            .delete_file("/path/to/restored_file".to_string())
+            .unwrap();
+
+        let deleted: Vec<String> = metadata
+            .list_deleted("/path/to")
+            .unwrap()
+            .into_iter()
+            .map(|(file_path, _)| file_path)
+            .collect();
+        assert_eq!(
+            deleted,
+            vec!["/path/to/deleted_file", "/path/to/restored_file"]
+        );
+        assert!(metadata.list_deleted("/path/to/new").unwrap().is_empty());
+
+        metadata
+            .restore_file("/path/to/restored_file".to_string())
+            .unwrap();
+
+        // Restored file keeps its chunks and is not purged
+        assert_eq!(
+            metadata.open_file("/path/to/restored_file").unwrap()[0].chunk_handle,
+            chunk_metadata.chunk_handle
+        );
+        assert!(matches!(
+            metadata.restore_file("/path/to/restored_file".to_string()),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(
+            metadata.collect_garbage(Duration::ZERO).unwrap(),
+            vec!["/path/to/deleted_file"]
+        );
+        assert!(matches!(
+            metadata.restore_file("/path/to/deleted_file".to_string()),
+            Err(Error::NotFound(_))
+        ));
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn rmdir_should_delete_only_empty_directory() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        assert!(matches!(
+            metadata.rmdir("/path/to/new", false),
+            Err(Error::DirectoryNotEmpty(_))
+        ));
+        assert!(matches!(
+            metadata.rmdir("/path/to/new/file", false),
+            Err(Error::NotADirectory(_))
+        ));
+        assert!(matches!(
+            metadata.rmdir("/", true),
+            Err(Error::InvalidPath { .. })
+        ));
+
+        metadata.rmdir("/path/to/new/directory", false).unwrap();
+        assert_eq!(metadata.ls("/path/to/new").unwrap(), vec!["file"]);
+        assert!(matches!(
+            metadata.rmdir("/path/to/new/directory", false),
+            Err(Error::NotFound(_))
+        ));
+
+        // Directory with only deleted files is empty, they are kept until purged
+        metadata
+            .create_file("/path/to/dir/file".to_string())
+            .unwrap();
+        metadata
+            .delete_file("/path/to/dir/file".to_string())
+            .unwrap();
+        metadata.rmdir("/path/to/dir", false).unwrap();
+
+        let mut content = metadata.ls("/path/to").unwrap();
+        content.sort();
+        assert_eq!(content, vec!["new"]);
+        assert!(matches!(
+            metadata.ls("/path/to/dir"),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(
+            metadata.list_deleted("/path/to/dir").unwrap()[0].0,
+            "/path/to/dir/file"
+        );
+
+        // Directory created again does not show deleted content
+        metadata.mkdir("/path/to/dir").unwrap();
+        assert!(metadata.ls("/path/to/dir").unwrap().is_empty());
+        assert_eq!(metadata.list_deleted("/path/to/dir").unwrap().len(), 1);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn recursive_delete_should_make_chunks_eligible_for_gc() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let mut chunk_handles: Vec<u64> = metadata
+            .open_file("/path/to/new/file")
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk.chunk_handle)
+            .collect();
+        chunk_handles.push(
+            allocate_chunk_of_deleted_file(&metadata, "/path/to/deleted_file", 0).chunk_handle,
+        );
+        chunk_handles.sort();
+
+        let heartbeat = || {
+            let mut to_delete = metadata.heartbeat_update(HeartbeatRequest {
+                server_address: "123".to_string(),
+                chunks: stored_chunks(&chunk_handles, 0),
+                ..Default::default()
+            });
+            to_delete.sort();
+            to_delete
+        };
+
+        metadata.rmdir("/path", true).unwrap();
+
+        assert!(metadata.ls("/").unwrap().is_empty());
+        assert!(matches!(
+            metadata.stat("/path/to/new/file", 1),
+            Err(Error::NotFound(_))
+        ));
+        let deleted: Vec<String> = metadata
+            .list_deleted("/")
+            .unwrap()
+            .into_iter()
+            .map(|(file_path, _)| file_path)
+            .collect();
+        assert_eq!(deleted, vec!["/path/to/deleted_file", "/path/to/new/file"]);
+
+        // Chunks are kept during grace period
+        assert!(heartbeat().is_empty());
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        // Restored file brings back its directories
+        metadata
+            .restore_file("/path/to/new/file".to_string())
+            .unwrap();
+        assert_eq!(metadata.ls("/path/to").unwrap(), vec!["new"]);
+        assert_eq!(metadata.ls("/path/to/new").unwrap(), vec!["file"]);
+
+        metadata.rmdir("/path", true).unwrap();
+        assert_eq!(metadata.collect_garbage(Duration::ZERO).unwrap().len(), 2);
+
+        // Hidden directories are removed with last purged file
+        assert_eq!(*metadata.namespace.lock().unwrap(), Namespace::new());
+        assert_eq!(heartbeat(), chunk_handles);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        metadata.checkpoint().unwrap();
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn file_replacing_deleted_directory_should_drop_its_chunks() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunk_handles: Vec<u64> = metadata
+            .open_file("/path/to/new/file")
+            .unwrap()
+            .iter()
+            .map(|chunk| chunk.chunk_handle)
+            .collect();
+
+        metadata.rmdir("/path/to/new", true).unwrap();
+        metadata.create_file("/path/to/new".to_string()).unwrap();
+
+        assert!(metadata.list_deleted("/path/to/new").unwrap().is_empty());
+
+        let mut to_delete = metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: stored_chunks(&chunk_handles, 0),
+            ..Default::default()
+        });
+        to_delete.sort();
+        assert_eq!(to_delete, chunk_handles);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    // Files in namespace, visible and deleted, are the ones with chunk mapping
+    fn assert_files_mapped(metadata: &Metadata) {
+        fn collect(node: &Node, path: &str, files: &mut Vec<String>) {
+            match node {
+                Node::Directory { nodes, .. } => {
+                    for (name, node) in nodes.iter() {
+                        collect(node, &format!("{}/{}", path, name), files);
+                    }
+                }
+                Node::File { .. } => files.push(path.to_string()),
+            }
+        }
+
+        let mut files = Vec::new();
+        collect(&metadata.namespace.lock().unwrap().root, "", &mut files);
+        files.sort();
+
+        let chunk_handles = metadata.filepath_to_chunk_handles.lock().unwrap();
+        let mut mapped: Vec<String> = chunk_handles.keys().cloned().collect();
+        mapped.sort();
+        assert_eq!(files, mapped);
+
+        let owners = metadata.chunk_handle_to_file.lock().unwrap();
+        assert_eq!(
+            owners.len(),
+            chunk_handles
+                .values()
+                .map(|handles| handles.len())
+                .sum::<usize>()
+        );
+        for (file_path, handles) in chunk_handles.iter() {
+            for chunk_handle in handles.values() {
+                assert_eq!(&owners[chunk_handle], file_path);
+            }
+        }
+    }
+
+    #[test]
+    fn rename_should_move_file_with_its_chunks() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunks = metadata.open_file("/path/to/new/file").unwrap();
+
+        metadata
+            .rename("/path/to/new/file", "/path/to/renamed", false)
+            .unwrap();
+
+        assert!(matches!(
+            metadata.stat("/path/to/new/file", 1),
+            Err(Error::NotFound(_))
+        ));
+        assert_eq!(metadata.open_file("/path/to/renamed").unwrap(), chunks);
+        assert_files_mapped(&metadata);
+
+        assert!(matches!(
+            metadata.rename("/path/to/new/file", "/path/to/other", false),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            metadata.rename("/", "/path/to/other", false),
+            Err(Error::InvalidPath { .. })
+        ));
+        assert!(matches!(
+            metadata.rename("/path/to", "/path/to/new/inside", false),
+            Err(Error::InvalidPath { .. })
+        ));
+        assert!(matches!(
+            metadata.rename("/path/to/new/directory", "/path/to/renamed/inside", false),
+            Err(Error::NotADirectory(_))
+        ));
+
+        // Temporary file replaces published one only with overwrite
+        metadata.create_file("/tmp/renamed".to_string()).unwrap();
+        let new_chunk = metadata.allocate_chunk("/tmp/renamed", 0).unwrap();
+
+        assert!(matches!(
+            metadata.rename("/tmp/renamed", "/path/to/renamed", false),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            metadata.rename("/tmp/renamed", "/path/to/new", true),
+            Err(Error::IsADirectory(_))
+        ));
+
+        metadata
+            .rename("/tmp/renamed", "/path/to/renamed", true)
+            .unwrap();
+        assert_eq!(
+            metadata.open_file("/path/to/renamed").unwrap(),
+            vec![new_chunk.clone()]
+        );
+        assert!(metadata.ls("/tmp").unwrap().is_empty());
+        assert_files_mapped(&metadata);
+
+        // Chunks of overwritten file are deleted
+        let mut old_handles: Vec<u64> = chunks.iter().map(|chunk| chunk.chunk_handle).collect();
+        let mut stored = old_handles.clone();
+        stored.push(new_chunk.chunk_handle);
+
+        let mut to_delete = metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: stored_chunks(&stored, 0),
+            ..Default::default()
+        });
+        to_delete.sort();
+        old_handles.sort();
+        assert_eq!(to_delete, old_handles);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn rename_should_move_directory_with_deleted_files() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        metadata.mkdir("/moved/dir").unwrap();
+        assert!(matches!(
+            metadata.rename("/path/to", "/moved", true),
+            Err(Error::DirectoryNotEmpty(_))
+        ));
+        metadata.rename("/path/to", "/moved/dir", true).unwrap();
+
+        // Missing parents of destination are created
+        metadata.rename("/moved/dir", "/a/b/c", false).unwrap();
+        assert!(metadata.ls("/path").unwrap().is_empty());
+        assert_eq!(metadata.ls("/a/b/c/new").unwrap().len(), 2);
+        assert_eq!(metadata.open_file("/a/b/c/new/file").unwrap().len(), 2);
+        assert_files_mapped(&metadata);
+
+        // Deleted file moved with directory is still purged
+        assert_eq!(
+            metadata.collect_garbage(Duration::ZERO).unwrap(),
+            vec!["/a/b/c/deleted_file"]
+        );
+        assert_files_mapped(&metadata);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        metadata.checkpoint().unwrap();
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn rename_should_be_atomic_with_concurrent_create_and_delete() {
+        const JOBS: u64 = 8;
+        const ROUNDS: u64 = 20;
+
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        thread::scope(|scope| {
+            for job in 0..JOBS {
+                let metadata = &metadata;
+
+                // Output is written under temporary name and published by rename
+                scope.spawn(move || {
+                    for round in 0..ROUNDS {
+                        let tmp_path = format!("/jobs/tmp/{}/part", job);
+                        let _ = metadata.create_file(tmp_path.clone());
+                        let _ = metadata.allocate_chunk(&tmp_path, round);
+
+                        match metadata.rename(&format!("/jobs/tmp/{}", job), "/jobs/out", true) {
+                            Ok(()) => {}
+                            Err(
+                                Error::NotFound(_)
+                                | Error::AlreadyExists(_)
+                                | Error::DirectoryNotEmpty(_),
+                            ) => {}
+                            Err(e) => panic!("Rename failed: {}", e),
+                        }
+                    }
+                });
+
+                // Other clients create and delete both paths in the meantime
+                scope.spawn(move || {
+                    for _ in 0..ROUNDS {
+                        let _ = metadata.create_file(format!("/jobs/tmp/{}/other", job));
+                        let _ = metadata.delete_file(format!("/jobs/tmp/{}/part", job));
+                        let _ = metadata.rmdir("/jobs/out", true);
+                        let _ = metadata.create_file("/jobs/out/file".to_string());
+                    }
+                });
+            }
+
+            scope.spawn(|| {
+                for _ in 0..10 {
+                    metadata.checkpoint().unwrap();
+                    metadata.collect_garbage(Duration::ZERO).unwrap();
+                }
+            });
+        });
+
+        assert_files_mapped(&metadata);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn corrupted_replica_should_be_dropped() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunk_handle = metadata.open_file("/path/to/new/file").unwrap()[0].chunk_handle;
+
+        let heartbeat = |server_address: &str, corrupted_chunks: Vec<u64>| {
+            // Corrupted chunk is not reported as stored
+            let chunks = if corrupted_chunks.is_empty() {
+                stored_chunks(&[chunk_handle], 0)
+            } else {
+                Vec::new()
+            };
+
+            metadata.heartbeat_update(HeartbeatRequest {
+                server_address: server_address.to_string(),
+                chunks,
+                corrupted_chunks,
+                ..Default::default()
+            })
+        };
+
+        assert!(heartbeat("123", Vec::new()).is_empty());
+        assert!(heartbeat("456", Vec::new()).is_empty());
+        metadata.acquire_lease(
+            chunk_handle,
+            "123",
+            SystemTime::now() + Duration::from_secs(60),
+        );
+
+        assert_eq!(heartbeat("123", vec![chunk_handle]), vec![chunk_handle]);
+
+        let chunk_metadata = &metadata.open_file("/path/to/new/file").unwrap()[0];
+        assert_eq!(chunk_metadata.locations, vec!["456".to_string()]);
+
+        // Lease is left to expire, primary may still hold it
+        assert_eq!(chunk_metadata.primary, "123");
+        assert!(metadata
+            .extend_leases("123", &[chunk_handle], SystemTime::now())
+            .is_empty());
+
+        // Last copy is kept, but it is not used
+        assert!(heartbeat("456", vec![chunk_handle]).is_empty());
+        assert!(metadata.open_file("/path/to/new/file").unwrap()[0]
+            .locations
+            .is_empty());
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn stale_replica_should_be_dropped() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let chunk_handle = metadata.open_file("/path/to/new/file").unwrap()[0].chunk_handle;
+
+        let heartbeat = |server_address: &str, version: u64| {
+            metadata.heartbeat_update(HeartbeatRequest {
+                server_address: server_address.to_string(),
+                chunks: stored_chunks(&[chunk_handle], version),
+                ..Default::default()
+            })
+        };
+
+        assert!(heartbeat("123", 0).is_empty());
+        assert!(heartbeat("456", 0).is_empty());
+
+        // Server 456 was down when chunk got new lease
+        metadata.bump_chunk_version(chunk_handle, 1).unwrap();
+        metadata.bump_chunk_version(chunk_handle, 2).unwrap();
+        // Version recorded by concurrent grant is not bumped again, older one does not lower it
+        metadata.bump_chunk_version(chunk_handle, 2).unwrap();
+        metadata.bump_chunk_version(chunk_handle, 1).unwrap();
+        assert_eq!(metadata.get_chunk_version(chunk_handle), 2);
+        assert!(heartbeat("123", 2).is_empty());
+        assert_eq!(heartbeat("456", 1), vec![chunk_handle]);
+
+        assert_eq!(
+            metadata.open_file("/path/to/new/file").unwrap()[0].locations,
+            vec!["123".to_string()]
+        );
+
+        // Clone started before version changed is not recorded
+        metadata.add_chunk_location(chunk_handle, "456", 1);
+        assert_eq!(
+            metadata.open_file("/path/to/new/file").unwrap()[0].locations,
+            vec!["123".to_string()]
+        );
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        metadata.checkpoint().unwrap();
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        // After restart stale replica is kept until up-to-date replica reports chunk
+        let heartbeat = |server_address: &str, version: u64| {
+            recovered.heartbeat_update(HeartbeatRequest {
+                server_address: server_address.to_string(),
+                chunks: stored_chunks(&[chunk_handle], version),
+                ..Default::default()
+            })
+        };
+
+        assert!(heartbeat("456", 1).is_empty());
+        assert!(recovered.open_file("/path/to/new/file").unwrap()[0]
+            .locations
+            .is_empty());
+        assert!(heartbeat("123", 2).is_empty());
+        assert_eq!(heartbeat("456", 1), vec![chunk_handle]);
+
+        // Newer version reported by replica is logged, so it is known after next restart
+        assert!(heartbeat("123", 3).is_empty());
+        assert_eq!(recovered.get_chunk_version(chunk_handle), 3);
+        assert_eq!(
+            Metadata::recover(&data_path)
+                .unwrap()
+                .get_chunk_version(chunk_handle),
+            3
+        );
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn file_size_should_follow_reported_chunk_sizes() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let file_path = "/path/to/new/file";
+        let chunks = metadata.open_file(file_path).unwrap();
+        let (first, second) = (chunks[0].chunk_handle, chunks[1].chunk_handle);
+
+        let stat = metadata.stat(file_path, 100).unwrap();
+        assert_eq!(stat.size, 0);
+        assert_eq!(stat.chunk_count, 2);
+        assert_eq!(stat.modified_at, stat.created_at);
+
+        // Chunk at index 1 is a hole, file ends in chunk at index 2
+        metadata.report_chunk_size(second, 10);
+        let stat = metadata.stat(file_path, 100).unwrap();
+        assert_eq!(stat.size, 210);
+        assert_eq!(
+            stat.chunks.iter().map(|c| c.size).collect::<Vec<_>>(),
+            vec![0, 10]
+        );
+        assert!(stat.modified_at >= stat.created_at);
+
+        // Delayed report does not shrink chunk, heartbeat fills missing size
+        metadata.report_chunk_size(second, 5);
+        metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: vec![StoredChunk {
+                chunk_handle: first,
+                size: 100,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        let stat = metadata.stat(file_path, 100).unwrap();
+        assert_eq!(stat.size, 210);
+        assert_eq!(
+            stat.chunks.iter().map(|c| c.size).collect::<Vec<_>>(),
+            vec![100, 10]
+        );
+
+        // Stale replica does not report size
+        metadata.bump_chunk_version(second, 1).unwrap();
+        metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: vec![StoredChunk {
+                chunk_handle: second,
+                size: 50,
+                ..Default::default()
+            }],
+            ..Default::default()
+        });
+        assert_eq!(metadata.stat(file_path, 100).unwrap().size, 210);
+
+        assert!(matches!(
+            metadata.stat("/path/to/deleted_file", 100),
+            Err(Error::NotFound(_))
+        ));
+        assert!(matches!(
+            metadata.stat("/path/to/new", 100),
+            Err(Error::IsADirectory(_))
+        ));
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_eq!(
+            recovered.stat(file_path, 100).unwrap().created_at,
+            stat.created_at
+        );
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn chunk_handles_should_never_be_reused() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let handles = |file_path: &str| -> Vec<u64> {
+            metadata
+                .open_file(file_path)
+                .unwrap()
+                .iter()
+                .map(|chunk| chunk.chunk_handle)
+                .collect()
+        };
+
+        let first = handles("/path/to/new/file");
+        assert!(first[0] < first[1]);
+
+        // Recreated file gets new handles, old ones stay with deleted file until it is purged
+        metadata
+            .delete_file("/path/to/new/file".to_string())
+            .unwrap();
+        metadata
+            .create_file("/path/to/new/file".to_string())
+            .unwrap();
+        metadata.allocate_chunk("/path/to/new/file", 0).unwrap();
+        metadata.allocate_chunk("/path/to/new/file", 1).unwrap();
+        let second = handles("/path/to/new/file");
+        assert!(first[1] < second[0] && second[0] < second[1]);
+
+        // Purged handles are not in checkpoint, counter still moves past them
+        metadata
+            .delete_file("/path/to/new/file".to_string())
+            .unwrap();
+        metadata.collect_garbage(Duration::ZERO).unwrap();
+        metadata.checkpoint().unwrap();
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        let server = ChunkServerStatus::new("123".to_string(), 0, 1000000, HashSet::new());
+        recovered
+            .chunk_servers
+            .lock()
+            .unwrap()
+            .insert(server.address.clone(), server);
+        recovered
+            .create_file("/path/to/new/file".to_string())
+            .unwrap();
+        let chunk_handle = recovered
+            .allocate_chunk("/path/to/new/file", 0)
+            .unwrap()
+            .chunk_handle;
+        assert!(second[1] < chunk_handle);
+
+        // Allocation after checkpoint is replayed from log
+        let replayed = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&recovered, &replayed);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn chunks_of_replaced_file_should_be_dropped() {
+        let data_path = test_data_path();
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let old = allocate_chunk_of_deleted_file(&metadata, "/path/to/deleted_file", 0);
+
+        metadata
+            .create_file("/path/to/deleted_file".to_string())
+            .unwrap();
+        let new = metadata.allocate_chunk("/path/to/deleted_file", 0).unwrap();
+
+        let to_delete = metadata.heartbeat_update(HeartbeatRequest {
+            server_address: "123".to_string(),
+            chunks: stored_chunks(&[old.chunk_handle, new.chunk_handle], 0),
+            ..Default::default()
+        });
+        assert_eq!(to_delete, vec![old.chunk_handle]);
+        assert_eq!(
+            metadata.chunk_handle_to_file.lock().unwrap()[&new.chunk_handle],
+            "/path/to/deleted_file"
+        );
+        assert!(!metadata
+            .chunk_handle_to_file
+            .lock()
+            .unwrap()
+            .contains_key(&old.chunk_handle));
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        metadata.checkpoint().unwrap();
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn path_locks_should_block_only_conflicting_operations() {
+        let locks = PathLocks::new();
+        let path = |path: &str| DfsPath::parse(path).unwrap();
+
+        let file = locks.lock(&path("/a/b/file"), LockMode::Write);
+
+        // Siblings, parent readers and other directories are not blocked
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _sibling = locks.lock(&path("/a/b/other"), LockMode::Write);
+                let _parent = locks.lock(&path("/a/b"), LockMode::Read);
+                let _other = locks.lock(&path("/c"), LockMode::Write);
+            });
+        });
+
+        // Parent can not be replaced while file is locked
+        let (locked_tx, locked_rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                let _parent = locks.lock(&path("/a/b"), LockMode::Write);
+                locked_tx.send(()).unwrap();
+            });
+
+            assert!(locked_rx.recv_timeout(Duration::from_millis(200)).is_err());
+            drop(file);
+            locked_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        });
+
+        // Paths requested in opposite order are locked in the same order
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..100 {
+                        let (x, y) = (path("/x/file"), path("/y/file"));
+                        let _locks = if i % 2 == 0 {
+                            locks.lock_all(&[(&x, LockMode::Write), (&y, LockMode::Write)])
+                        } else {
+                            locks.lock_all(&[(&y, LockMode::Write), (&x, LockMode::Write)])
+                        };
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn concurrent_namespace_operations_should_keep_metadata_consistent() {
+        const WORKERS: u64 = 8;
+        const FILES: u64 = 20;
+
+        let data_path = test_data_path();
+        let metadata = Arc::new(Metadata::recover(&data_path).unwrap());
+        populate(&metadata);
+
+        let (done_tx, done_rx) = mpsc::channel();
+
+        let stress = {
+            let metadata = metadata.clone();
+
+            thread::spawn(move || {
+                thread::scope(|scope| {
+                    for worker in 0..WORKERS {
+                        let metadata = &metadata;
+
+                        scope.spawn(move || {
+                            for i in 0..FILES {
+                                // Every worker has its own directory
+                                let file_path = format!("/stress/dir{}/sub/file{}", worker, i);
+                                metadata.mkdir(&format!("/stress/dir{}", worker)).unwrap();
+                                metadata.create_file(file_path.clone()).unwrap();
+
+                                let chunk = metadata.allocate_chunk(&file_path, 0).unwrap();
+                                let version = metadata.get_chunk_version(chunk.chunk_handle) + 1;
+                                metadata
+                                    .bump_chunk_version(chunk.chunk_handle, version)
+                                    .unwrap();
+
+                                if i % 2 == 0 {
+                                    metadata.delete_file(file_path.clone()).unwrap();
+                                }
+                                if i % 4 == 0 {
+                                    // Garbage collection can purge file first
+                                    match metadata.restore_file(file_path) {
+                                        Ok(()) | Err(Error::NotFound(_)) => {}
+                                        Err(e) => panic!("Restore failed: {}", e),
+                                    }
+                                }
+
+                                // Workers race for the same names in shared directory
+                                let shared_path = format!("/stress/shared/file{}", i);
+                                match metadata.create_file(shared_path.clone()) {
+                                    Ok(()) | Err(Error::AlreadyExists(_)) => {}
+                                    Err(e) => panic!("Create failed: {}", e),
+                                }
+                                metadata.allocate_chunk(&shared_path, worker).unwrap();
+                                metadata.ls("/stress/shared").unwrap();
+                            }
+                        });
+                    }
+
+                    // Checkpoints and purges run while namespace is changed
+                    scope.spawn(|| {
+                        for _ in 0..20 {
+                            metadata.checkpoint().unwrap();
+                            metadata.collect_garbage(Duration::ZERO).unwrap();
+                            thread::sleep(Duration::from_millis(1));
+                        }
+                    });
+                });
+
+                done_tx.send(()).unwrap();
+            })
+        };
+
+        if let Err(RecvTimeoutError::Timeout) = done_rx.recv_timeout(Duration::from_secs(60)) {
+            panic!("Namespace operations deadlocked");
+        }
+        stress.join().unwrap();
+
+        for worker in 0..WORKERS {
+            for i in (1..FILES).step_by(2) {
+                let file_path = format!("/stress/dir{}/sub/file{}", worker, i);
+                assert_eq!(metadata.open_file(&file_path).unwrap().len(), 1);
+            }
+        }
+
+        for i in 0..FILES {
+            let shared_path = format!("/stress/shared/file{}", i);
+            assert_eq!(
+                metadata.open_file(&shared_path).unwrap().len(),
+                WORKERS as usize
+            );
+        }
+
+        // Every chunk has single owner that maps it
+        let files = metadata.filepath_to_chunk_handles.lock().unwrap().clone();
+        let owners = metadata.chunk_handle_to_file.lock().unwrap().clone();
+        assert_eq!(
+            owners.len(),
+            files.values().map(|handles| handles.len()).sum::<usize>()
+        );
+        for (file_path, handles) in files.iter() {
+            for chunk_handle in handles.values() {
+                assert_eq!(&owners[chunk_handle], file_path);
+            }
+        }
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        fs::remove_dir_all(data_path).unwrap();
+    }
+
+    #[test]
+    fn restarted_metadata_should_replay_operation_log() {
+        let data_path = test_data_path();
+
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+
+        assert_same_metadata(&metadata, &recovered);
+        assert_eq!(recovered.ls("/path/to").unwrap().len(), 1);
+        assert_eq!(recovered.ls("/path/to/new").unwrap().len(), 2);
+
+        // Recovered metadata should keep logging new operations
+        recovered.mkdir("/other").unwrap();
+        drop(recovered);
+
+        let recovered_again = Metadata::recover(&data_path).unwrap();
+        assert_eq!(recovered_again.ls("/path").unwrap().len(), 1);
+        assert_eq!(recovered_again.ls("/other").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn incomplete_log_entry_should_be_dropped_on_recovery() {
+        let data_path = test_data_path();
+
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+        drop(metadata);
+
+        // Deletion times are logged, so expected state is recovered from copy of complete log
+        let expected_path = test_data_path();
+        fs::copy(
+            data_path.join("operation_log.0"),
+            expected_path.join("operation_log.0"),
+        )
+        .unwrap();
+        let expected = Metadata::recover(&expected_path).unwrap();
+
+        // Simulate crash in the middle of append
+        append_to_log(&data_path, b"{\"Mkdir\":{\"pa");
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&expected, &recovered);
+
+        recovered.mkdir("/after_crash").unwrap();
+        drop(recovered);
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_eq!(recovered.ls("/after_crash").unwrap().len(), 0);
+    }
+
+    #[test]
+    fn recovery_should_load_checkpoint_and_replay_log_tail() {
+        let data_path = test_data_path();
+
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+        metadata.checkpoint().unwrap();
+
+        // Operations included in checkpoint should be removed from log
+        assert!(!data_path.join("operation_log.0").exists());
+        assert!(data_path.join("checkpoint.1").exists());
+
+        metadata.mkdir("/after/checkpoint").unwrap();
+        metadata
+            .create_file("/after/checkpoint/file".to_string())
+            .unwrap();
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+
+        // Second checkpoint should replace the first one
+        recovered.checkpoint().unwrap();
+        assert!(!data_path.join("checkpoint.1").exists());
+        assert!(!data_path.join("operation_log.1").exists());
+
+        let recovered_again = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered_again);
+    }
+
+    #[test]
+    fn broken_checkpoint_should_be_skipped() {
+        let data_path = test_data_path();
+
+        let metadata = Metadata::recover(&data_path).unwrap();
+        populate(&metadata);
+        metadata.checkpoint().unwrap();
+        metadata.mkdir("/after/checkpoint").unwrap();
+
+        // Newer checkpoint left half-written by crash, logs were not removed yet
+        let checkpoint = fs::read(data_path.join("checkpoint.1")).unwrap();
+        fs::write(
+            data_path.join("checkpoint.2"),
+            &checkpoint[..checkpoint.len() / 2],
+        )
+        .unwrap();
+        fs::write(data_path.join("checkpoint.3.tmp"), b"{").unwrap();
+
+        let recovered = Metadata::recover(&data_path).unwrap();
+        assert_same_metadata(&metadata, &recovered);
+        assert!(!data_path.join("checkpoint.3.tmp").exists());
+    }
+
+    fn append_to_log(data_path: &Path, data: &[u8]) {
+        let mut log = fs::OpenOptions::new()
+            .append(true)
+            .open(data_path.join("operation_log.0"))
+            .unwrap();
+        log.write_all(data).unwrap();
+    }
 }
@@ -1,6 +1,11 @@
 use std::collections::HashMap;
 
-#[derive(Debug)]
+use common::path::DfsPath;
+use serde::{Deserialize, Serialize};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Namespace {
     pub root: Node,
 }
@@ -11,209 +16,566 @@ impl Namespace {
             root: Node::Directory {
                 name: "".to_string(),
                 nodes: HashMap::new(),
+                status: Status::Active,
             },
         }
     }
 
-    //TODO: fix &mut self unnecessary
-    pub fn is_active(&mut self, file_path: &str) -> bool {
+    // Missing parent directories are created. Returns deleted files that were dropped,
+    // because hidden node with their path or their directory was replaced.
+    pub fn create_file(&mut self, file_path: &str, created_at: u64) -> Result<Vec<String>, Error> {
+        let mut parts = components(file_path)?;
+
+        let name = parts
+            .pop()
+            .ok_or_else(|| Error::AlreadyExists(file_path.to_string()))?;
+
         let mut node = &mut self.root;
-        let file_path = file_path.strip_prefix('/').unwrap();
+        let mut path = String::new();
+        let mut dropped = Vec::new();
 
-        for part in file_path.split('/') {
-            // Add validation
-            if part.is_empty() {
-                // Error(Invalid dir name)
-            }
+        for part in parts {
+            path = format!("{}/{}", path, part);
 
-            node = node.get_node(part);
+            // mkdir if not exists else traverse
+            node = node
+                .mkdir(&part, &path, &mut dropped)
+                .ok_or_else(|| Error::NotADirectory(file_path.to_string()))?;
         }
 
-        match node {
-            Node::Directory { name, nodes } => {
-                // ignore for now, rethink folder deletion later
-                todo!()
-            }
-            Node::File { name, status } => match status {
-                Status::Active => true,
-                Status::Deleted => false,
+        if !node.create_file(&name, file_path, created_at, &mut dropped) {
+            return Err(Error::AlreadyExists(file_path.to_string()));
+        }
+
+        Ok(dropped)
+    }
+
+    // File is only hidden, it is purged by garbage collection after grace period
+    pub fn delete_file(&mut self, file_path: &str, deleted_at: u64) -> Result<(), Error> {
+        match self.get_node_mut(file_path)? {
+            Node::Directory { .. } => Err(Error::IsADirectory(file_path.to_string())),
+            Node::File { status, .. } => match status {
+                Status::Active => {
+                    *status = Status::Deleted { deleted_at };
+                    Ok(())
+                }
+                Status::Deleted { .. } => Err(Error::NotFound(file_path.to_string())),
             },
         }
     }
 
-    pub fn create_file(&mut self, file_path: &str) {
+    // Brings back deleted file, its chunks are still mapped until it is purged.
+    // Hidden directories on its path are visible again, their other content stays deleted.
+    pub fn restore_file(&mut self, file_path: &str) -> Result<(), Error> {
+        match self.get_node_mut(file_path)? {
+            Node::Directory { .. } => return Err(Error::IsADirectory(file_path.to_string())),
+            Node::File { status, .. } => match status {
+                Status::Deleted { .. } => *status = Status::Active,
+                Status::Active => return Err(Error::NotFound(file_path.to_string())),
+            },
+        }
+
         let mut node = &mut self.root;
-        let file_path = file_path.strip_prefix('/').unwrap();
-        if let Some((path, name)) = file_path.rsplit_once('/') {
-            for part in path.split('/') {
-                // Add validation
-                if part.is_empty() {
-                    // Error(Invalid dir name)
+
+        for part in components(file_path)? {
+            node = match node {
+                Node::Directory { nodes, status, .. } => {
+                    *status = Status::Active;
+                    nodes
+                        .get_mut(&part)
+                        .ok_or_else(|| Error::NotFound(file_path.to_string()))?
                 }
+                Node::File { .. } => return Err(Error::NotADirectory(file_path.to_string())),
+            };
+        }
 
-                // mkdir if not exists else traverse
-                node = node.mkdir(part).unwrap();
+        Ok(())
+    }
+
+    // Hides directory and deletes files under it, they are purged by garbage collection.
+    // Directory without deleted files left under it is removed at once.
+    // Without recursive flag directory with visible content is not deleted.
+    pub fn delete_directory(
+        &mut self,
+        path: &str,
+        deleted_at: u64,
+        recursive: bool,
+    ) -> Result<(), Error> {
+        let mut parts = components(path)?;
+
+        let name = parts.pop().ok_or_else(|| Error::InvalidPath {
+            path: path.to_string(),
+            reason: "root directory can not be deleted".to_string(),
+        })?;
+
+        match self.get_node(path)? {
+            Node::Directory { .. } if recursive => {}
+            node @ Node::Directory { .. } => {
+                if !node.ls().is_empty() {
+                    return Err(Error::DirectoryNotEmpty(path.to_string()));
+                }
             }
+            Node::File { .. } => return Err(Error::NotADirectory(path.to_string())),
+        }
+
+        match self.get_node_mut(&format!("/{}", parts.join("/")))? {
+            Node::Directory { nodes, .. } => {
+                let node = nodes
+                    .get_mut(&name)
+                    .ok_or_else(|| Error::NotFound(path.to_string()))?;
+
+                if node.delete_subtree(deleted_at) {
+                    nodes.remove(&name);
+                }
 
-            node.create_file(name);
+                Ok(())
+            }
+            Node::File { .. } => Err(Error::NotADirectory(path.to_string())),
         }
     }
 
-    pub fn delete_file(&mut self, file_path: &str) {
-        let mut node = &mut self.root;
-        let file_path = file_path.strip_prefix('/').unwrap();
+    // Moves file or directory with everything under it, deleted files included.
+    // Missing parent directories of destination are created. Existing destination is
+    // replaced only with overwrite flag, directory only if it has no visible content.
+    // Returns deleted files and overwritten file that were dropped with replaced nodes.
+    pub fn rename(&mut self, src: &str, dst: &str, overwrite: bool) -> Result<Vec<String>, Error> {
+        let mut src_parts = components(src)?;
+        let mut dst_parts = components(dst)?;
+
+        let root_error = |path: &str| Error::InvalidPath {
+            path: path.to_string(),
+            reason: "root directory can not be renamed".to_string(),
+        };
+        let src_name = src_parts.pop().ok_or_else(|| root_error(src))?;
+        let dst_name = dst_parts.pop().ok_or_else(|| root_error(dst))?;
+
+        let is_directory = match self.get_node(src)? {
+            Node::Directory { .. } => true,
+            Node::File { .. } => false,
+        };
+
+        if src == dst {
+            return Ok(Vec::new());
+        }
+
+        if dst.starts_with(&format!("{}/", src)) {
+            return Err(Error::InvalidPath {
+                path: dst.to_string(),
+                reason: "destination is inside renamed directory".to_string(),
+            });
+        }
+
+        // Everything is checked before namespace is changed, so failed rename changes nothing
+        if let Some(node) = self.find_destination(dst)? {
+            if !overwrite {
+                return Err(Error::AlreadyExists(dst.to_string()));
+            }
 
-        for part in file_path.split('/') {
-            // Add validation
-            if part.is_empty() {
-                // Error(Invalid dir name)
+            match node {
+                Node::Directory { .. } if !is_directory => {
+                    return Err(Error::IsADirectory(dst.to_string()))
+                }
+                Node::File { .. } if is_directory => {
+                    return Err(Error::NotADirectory(dst.to_string()))
+                }
+                Node::Directory { .. } if !node.ls().is_empty() => {
+                    return Err(Error::DirectoryNotEmpty(dst.to_string()))
+                }
+                _ => {}
             }
+        }
 
-            node = node.get_node(part);
+        let mut dropped = Vec::new();
+
+        let dst_parent = format!("/{}", dst_parts.join("/"));
+        self.mkdir_with_dropped(&dst_parent, &mut dropped)?;
+
+        let mut node = match self.get_node_mut(&format!("/{}", src_parts.join("/")))? {
+            Node::Directory { nodes, .. } => nodes
+                .remove(&src_name)
+                .ok_or_else(|| Error::NotFound(src.to_string()))?,
+            Node::File { .. } => return Err(Error::NotADirectory(src.to_string())),
+        };
+        node.set_name(&dst_name);
+
+        if let Node::Directory { nodes, .. } = self.get_node_mut(&dst_parent)? {
+            if let Some(replaced) = nodes.insert(dst_name, node) {
+                replaced.collect_files(dst, &mut dropped);
+            }
         }
-        node.mark_as_deleted();
+
+        Ok(dropped)
     }
 
-    // Path should always start with root
-    pub fn mkdir(&mut self, path: &str) {
-        let mut node = &mut self.root;
-        let path = path.strip_prefix('/').unwrap();
+    // Removes node of file deleted at given time. Active file or file that was
+    // restored and deleted again in the meantime is not purged.
+    pub fn purge_file(&mut self, file_path: &str, deleted_at: u64) -> Result<(), Error> {
+        let mut parts = components(file_path)?;
+
+        let name = parts
+            .pop()
+            .ok_or_else(|| Error::IsADirectory(file_path.to_string()))?;
+
+        let node = self.get_node_mut(&format!("/{}", parts.join("/")))?;
+
+        match node {
+            Node::Directory { nodes, .. } => match nodes.get(&name) {
+                Some(Node::File {
+                    status: Status::Deleted { deleted_at: time },
+                    ..
+                }) if *time == deleted_at => {
+                    nodes.remove(&name);
+                }
+                _ => return Err(Error::NotFound(file_path.to_string())),
+            },
+            Node::File { .. } => return Err(Error::NotADirectory(file_path.to_string())),
+        }
+
+        // Hidden directory is removed with last file purged from it
+        while let Some(name) = parts.pop() {
+            if let Node::Directory { nodes, .. } =
+                self.get_node_mut(&format!("/{}", parts.join("/")))?
+            {
+                let is_hidden_and_empty = matches!(
+                    nodes.get(&name),
+                    Some(Node::Directory {
+                        nodes: children,
+                        status: Status::Deleted { .. },
+                        ..
+                    }) if children.is_empty()
+                );
 
-        for part in path.split('/') {
-            // Add validation
-            if part.is_empty() {
-                // Error(Invalid dir name)
+                if !is_hidden_and_empty {
+                    break;
+                }
+
+                nodes.remove(&name);
             }
-            node = node.mkdir(part).unwrap();
         }
+
+        Ok(())
+    }
+
+    // Returns deleted files under given path with their deletion time, ordered by path
+    pub fn deleted_files(&self, path: &str) -> Result<Vec<(String, u64)>, Error> {
+        let path = DfsPath::parse(path)?;
+        let prefix = if path.is_root() { "" } else { path.as_str() };
+
+        let mut deleted = Vec::new();
+
+        self.find_node(path.as_str())?
+            .collect_deleted(prefix, &mut deleted);
+
+        deleted.sort();
+
+        Ok(deleted)
     }
 
-    pub fn ls(&self, path: &str) -> Vec<&str> {
-        // TODO: Add separate method to validate path
+    // Path should always start with root, missing parent directories are created.
+    // Returns deleted files that were dropped, because directory replaced them.
+    pub fn mkdir(&mut self, path: &str) -> Result<Vec<String>, Error> {
+        let mut dropped = Vec::new();
 
-        let path = path.strip_prefix('/').unwrap();
+        self.mkdir_with_dropped(path, &mut dropped)?;
+
+        Ok(dropped)
+    }
+
+    fn mkdir_with_dropped(&mut self, path: &str, dropped: &mut Vec<String>) -> Result<(), Error> {
+        let mut node = &mut self.root;
+        let mut node_path = String::new();
+
+        for part in components(path)? {
+            node_path = format!("{}/{}", node_path, part);
+
+            node = node
+                .mkdir(&part, &node_path, dropped)
+                .ok_or_else(|| Error::NotADirectory(path.to_string()))?;
+        }
+
+        Ok(())
+    }
 
+    // Returns visible node that would be replaced by node moved to path. Fails if path
+    // goes through visible file, hidden nodes on path are replaced or made visible.
+    fn find_destination(&self, path: &str) -> Result<Option<&Node>, Error> {
         let mut node = &self.root;
-        for part in path.split('/') {
-            // Add validation
-            if part.is_empty() {
-                // Error(Invalid dir name)
-            }
 
+        for part in components(path)? {
             node = match node {
-                Node::Directory { name, nodes } => {
-                    match nodes.get(part) {
-                        Some(node) => node,
-                        None => {
-                            // TODO: Error
-                            todo!()
-                        }
-                    }
-                }
-                Node::File { .. } => {
-                    // Error
-                    todo!()
-                }
+                Node::Directory { nodes, .. } => match nodes.get(&part) {
+                    Some(child) => child,
+                    None => return Ok(None),
+                },
+                Node::File {
+                    status: Status::Active,
+                    ..
+                } => return Err(Error::NotADirectory(path.to_string())),
+                // Deleted file is replaced by directory
+                Node::File { .. } => return Ok(None),
+            };
+        }
+
+        Ok(node.is_visible().then_some(node))
+    }
+
+    // Fails unless path is visible file, deleted file can be only restored or purged
+    pub fn check_file(&self, file_path: &str) -> Result<(), Error> {
+        self.created_at(file_path).map(|_| ())
+    }
+
+    // Creation time of visible file in ms since epoch
+    pub fn created_at(&self, file_path: &str) -> Result<u64, Error> {
+        match self.get_node(file_path)? {
+            Node::Directory { .. } => Err(Error::IsADirectory(file_path.to_string())),
+            Node::File { created_at, .. } => Ok(*created_at),
+        }
+    }
+
+    pub fn ls(&self, path: &str) -> Result<Vec<&str>, Error> {
+        match self.get_node(path)? {
+            node @ Node::Directory { .. } => Ok(node.ls()),
+            Node::File { .. } => Err(Error::NotADirectory(path.to_string())),
+        }
+    }
+
+    // Deleted files and directories are not visible
+    fn get_node(&self, path: &str) -> Result<&Node, Error> {
+        match self.find_node(path)? {
+            Node::File {
+                status: Status::Deleted { .. },
+                ..
             }
+            | Node::Directory {
+                status: Status::Deleted { .. },
+                ..
+            } => Err(Error::NotFound(path.to_string())),
+            node => Ok(node),
         }
+    }
 
-        node.ls()
+    // Returns deleted files too
+    fn find_node(&self, path: &str) -> Result<&Node, Error> {
+        let mut node = &self.root;
+
+        for part in components(path)? {
+            node = match node {
+                Node::Directory { nodes, .. } => nodes
+                    .get(&part)
+                    .ok_or_else(|| Error::NotFound(path.to_string()))?,
+                Node::File { .. } => return Err(Error::NotADirectory(path.to_string())),
+            };
+        }
+
+        Ok(node)
+    }
+
+    // Returns deleted files too
+    pub(super) fn get_node_mut(&mut self, path: &str) -> Result<&mut Node, Error> {
+        let mut node = &mut self.root;
+
+        for part in components(path)? {
+            node = match node {
+                Node::Directory { nodes, .. } => nodes
+                    .get_mut(&part)
+                    .ok_or_else(|| Error::NotFound(path.to_string()))?,
+                Node::File { .. } => return Err(Error::NotADirectory(path.to_string())),
+            };
+        }
+
+        Ok(node)
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+// Validates path and splits it into names of nodes, root has no components
+fn components(path: &str) -> Result<Vec<String>, Error> {
+    let path = DfsPath::parse(path)?;
+
+    Ok(path.components().map(str::to_string).collect())
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Status {
-    // Rethink this
+    #[default]
     Active,
-    Deleted,
+    // Milliseconds since unix epoch
+    Deleted {
+        deleted_at: u64,
+    },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Node {
     Directory {
         name: String,
         nodes: HashMap<String, Node>,
+        // Deleted directory is hidden until deleted files under it are purged
+        #[serde(default)]
+        status: Status,
     },
     File {
         name: String, // Do i need chunks stored here or in separate map<file_path/file_name, chunks>
         status: Status,
+        // Milliseconds since unix epoch
+        #[serde(default)]
+        created_at: u64,
     },
 }
 
 impl Node {
-    fn mkdir(&mut self, name: &str) -> Result<&mut Node, Error> {
+    // Returns existing or new child directory with given path, None if self or child is a file.
+    // Deleted file is hidden, so its name can be reused and it is added to dropped.
+    // Deleted directory is visible again, its content stays deleted.
+    fn mkdir(&mut self, name: &str, path: &str, dropped: &mut Vec<String>) -> Option<&mut Node> {
         match self {
             Node::Directory { nodes, .. } => {
                 let node = nodes.entry(name.to_string()).or_insert(Node::Directory {
                     name: name.to_string(),
                     nodes: HashMap::new(),
+                    status: Status::Active,
                 });
 
-                Ok(node)
-            }
-            Node::File { .. } => {
-                // TODO: Error(Its not directory)
-                Err(Error::new(
-                    ErrorKind::Other,
-                    "Path invalid. File not a directory.",
-                ))
+                match node {
+                    Node::File {
+                        status: Status::Deleted { .. },
+                        ..
+                    } => {
+                        dropped.push(path.to_string());
+
+                        *node = Node::Directory {
+                            name: name.to_string(),
+                            nodes: HashMap::new(),
+                            status: Status::Active,
+                        };
+                    }
+                    Node::Directory { status, .. } => *status = Status::Active,
+                    Node::File { .. } => {}
+                }
+
+                match node {
+                    Node::Directory { .. } => Some(node),
+                    Node::File { .. } => None,
+                }
             }
+            Node::File { .. } => None,
         }
     }
 
     fn ls(&self) -> Vec<&str> {
         match self {
-            Node::Directory { name, nodes } => nodes
+            Node::Directory { nodes, .. } => nodes
                 .values()
                 .filter_map(|node| match node {
-                    Node::Directory { name, nodes } => Some(name.as_str()),
-                    Node::File { name, status } => match status {
-                        Status::Deleted => None,
-                        Status::Active => Some(name.as_str()),
-                    },
+                    Node::Directory { name, status, .. } | Node::File { name, status, .. } => {
+                        match status {
+                            Status::Deleted { .. } => None,
+                            Status::Active => Some(name.as_str()),
+                        }
+                    }
                 })
                 .collect(),
-            Node::File { .. } => {
-                //TODO: Error
-                Vec::new()
-            }
+            Node::File { .. } => Vec::new(),
         }
     }
 
-    fn create_file(&mut self, file_name: &str) {
+    // Returns false if node with given name is already visible in directory.
+    // Deleted files replaced by new one are added to dropped.
+    fn create_file(
+        &mut self,
+        file_name: &str,
+        file_path: &str,
+        created_at: u64,
+        dropped: &mut Vec<String>,
+    ) -> bool {
         match self {
             Node::Directory { nodes, .. } => {
-                nodes.entry(file_name.to_string()).or_insert(Node::File {
-                    name: file_name.to_string(),
-                    status: Status::Active,
-                });
+                if let Some(node) = nodes.get(file_name) {
+                    if node.is_visible() {
+                        return false;
+                    }
+
+                    let mut deleted = Vec::new();
+                    node.collect_deleted(file_path, &mut deleted);
+                    dropped.extend(deleted.into_iter().map(|(path, _)| path));
+                }
+
+                nodes.insert(
+                    file_name.to_string(),
+                    Node::File {
+                        name: file_name.to_string(),
+                        status: Status::Active,
+                        created_at,
+                    },
+                );
+
+                true
             }
-            Node::File { .. } => {
-                //TODO: Error
+            Node::File { .. } => false,
+        }
+    }
+
+    fn set_name(&mut self, new_name: &str) {
+        match self {
+            Node::Directory { name, .. } | Node::File { name, .. } => *name = new_name.to_string(),
+        }
+    }
+
+    fn is_visible(&self) -> bool {
+        match self {
+            Node::Directory { status, .. } | Node::File { status, .. } => *status == Status::Active,
+        }
+    }
+
+    // Marks visible files under directory as deleted and hides it.
+    // Returns true when no deleted file is left under node, so it can be removed.
+    fn delete_subtree(&mut self, deleted_at: u64) -> bool {
+        match self {
+            Node::Directory { nodes, status, .. } => {
+                nodes.retain(|_, node| !node.delete_subtree(deleted_at));
+                *status = Status::Deleted { deleted_at };
+                nodes.is_empty()
+            }
+            Node::File { status, .. } => {
+                if *status == Status::Active {
+                    *status = Status::Deleted { deleted_at };
+                }
+                false
             }
         }
     }
 
-    fn mark_as_deleted(&mut self) {
+    // Paths of all files under node, visible and deleted
+    fn collect_files(&self, path: &str, files: &mut Vec<String>) {
         match self {
             Node::Directory { nodes, .. } => {
-                // Error for now, what about deleting dirs?
-                todo!()
+                for node in nodes.values() {
+                    let name = match node {
+                        Node::Directory { name, .. } | Node::File { name, .. } => name,
+                    };
+
+                    node.collect_files(&format!("{}/{}", path, name), files);
+                }
             }
-            Node::File { status, .. } => *status = Status::Deleted,
+            Node::File { .. } => files.push(path.to_string()),
         }
     }
 
-    pub fn get_node(&mut self, name: &str) -> &mut Node {
+    fn collect_deleted(&self, path: &str, deleted: &mut Vec<(String, u64)>) {
         match self {
             Node::Directory { nodes, .. } => {
-                // Error handling
-                nodes.get_mut(name).unwrap()
-            }
-            Node::File { .. } => {
-                // Error:
-                todo!()
+                for node in nodes.values() {
+                    let name = match node {
+                        Node::Directory { name, .. } | Node::File { name, .. } => name,
+                    };
+
+                    node.collect_deleted(&format!("{}/{}", path, name), deleted);
+                }
             }
+            Node::File {
+                status: Status::Deleted { deleted_at },
+                ..
+            } => deleted.push((path.to_string(), *deleted_at)),
+            Node::File { .. } => {}
         }
     }
 }
@@ -1,8 +1,241 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+// Log is split into files: operation_log.<generation>.
+// Generation is increased when checkpoint is taken, so checkpoint.<N>
+// contains state after applying all logs with generation lower than N.
+const OPERATION_LOG_PREFIX: &str = "operation_log.";
+
+// Every metadata mutation is stored as single json line
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    Mkdir {
+        path: String,
+    },
+    CreateFile {
+        file_path: String,
+        // Milliseconds since unix epoch
+        #[serde(default)]
+        created_at: u64,
+    },
+    DeleteFile {
+        file_path: String,
+        // Milliseconds since unix epoch
+        #[serde(default)]
+        deleted_at: u64,
+    },
+    // Directory with all files under it deleted at once
+    DeleteDirectory {
+        path: String,
+        // Milliseconds since unix epoch
+        deleted_at: u64,
+        recursive: bool,
+    },
+    // File or directory moved with everything under it
+    Rename {
+        src: String,
+        dst: String,
+        overwrite: bool,
+    },
+    // Deleted file brought back before it was purged
+    RestoreFile {
+        file_path: String,
+    },
+    // Deleted file removed by garbage collection
+    PurgeFile {
+        file_path: String,
+        // Deletion time of purged file, so file deleted again after restore is kept
+        deleted_at: u64,
+    },
+    AllocateChunk {
+        file_path: String,
+        chunk_index: u64,
+        chunk_handle: u64,
+    },
+    // Raises version of chunk when new lease is granted or replica reports newer one.
+    // Version is absolute, so bumps replayed in any order end with the highest one.
+    BumpChunkVersion {
+        chunk_handle: u64,
+        // Missing in entries logged before versions were absolute, they increment version
+        #[serde(default)]
+        version: u64,
+    },
+}
+
 #[derive(Debug)]
-pub struct OperationLog {}
+pub struct OperationLog {
+    data_path: PathBuf,
+    generation: u64,
+    file: File,
+    // Number of operations not included in any checkpoint yet
+    operations: usize,
+}
 
 impl OperationLog {
-    pub fn new() -> Self {
-        OperationLog {}
+    // Opens log files starting from given generation and returns operations stored in them.
+    // Last generation (created if missing) is used for appending.
+    pub fn open(data_path: &Path, from_generation: u64) -> Result<(Self, Vec<Operation>), Error> {
+        let generations: Vec<u64> = list_generations(data_path, OPERATION_LOG_PREFIX)?
+            .into_iter()
+            .filter(|generation| *generation >= from_generation)
+            .collect();
+
+        // Logs older than checkpoint are removed only after checkpoint is persisted,
+        // so missing generation means that some operations are lost
+        if let Some(first) = generations.first() {
+            if *first != from_generation {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "Operation log generation: {} missing, first found: {}",
+                        from_generation, first
+                    ),
+                ));
+            }
+        }
+
+        let mut operations = Vec::new();
+
+        for generation in generations.iter() {
+            operations.extend(read_operations(&log_path(data_path, *generation))?);
+        }
+
+        let generation = generations.last().copied().unwrap_or(from_generation);
+        let file = open_for_append(data_path, generation)?;
+
+        info!(
+            "Operation log opened, generations: {:?}, operations: {}",
+            generations,
+            operations.len()
+        );
+
+        let operation_log = OperationLog {
+            data_path: data_path.to_path_buf(),
+            generation,
+            file,
+            operations: operations.len(),
+        };
+
+        Ok((operation_log, operations))
     }
+
+    // Returns after operation is flushed to disk
+    pub fn append(&mut self, operation: &Operation) -> Result<(), Error> {
+        let mut line = serde_json::to_vec(operation)?;
+        line.push(b'\n');
+
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+
+        self.operations += 1;
+
+        Ok(())
+    }
+
+    // Switches appending to next generation and returns it
+    pub fn rotate(&mut self) -> Result<u64, Error> {
+        let generation = self.generation + 1;
+
+        self.file = open_for_append(&self.data_path, generation)?;
+        self.generation = generation;
+        self.operations = 0;
+
+        Ok(generation)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.operations == 0
+    }
+
+    pub fn data_path(&self) -> &Path {
+        &self.data_path
+    }
+}
+
+// Removes logs already included in checkpoint with given generation
+pub fn remove_before(data_path: &Path, generation: u64) -> Result<(), Error> {
+    for old_generation in list_generations(data_path, OPERATION_LOG_PREFIX)? {
+        if old_generation < generation {
+            fs::remove_file(log_path(data_path, old_generation))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Returns sorted generations of files named <prefix><generation>
+pub fn list_generations(data_path: &Path, prefix: &str) -> Result<Vec<u64>, Error> {
+    let mut generations = Vec::new();
+
+    for entry in fs::read_dir(data_path)? {
+        let file_name = entry?.file_name();
+
+        let generation = file_name
+            .to_str()
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|generation| generation.parse().ok());
+
+        if let Some(generation) = generation {
+            generations.push(generation);
+        }
+    }
+
+    generations.sort();
+
+    Ok(generations)
+}
+
+// Makes renames and newly created files in directory durable
+pub fn sync_dir(data_path: &Path) -> Result<(), Error> {
+    File::open(data_path)?.sync_all()
+}
+
+fn log_path(data_path: &Path, generation: u64) -> PathBuf {
+    data_path.join(format!("{}{}", OPERATION_LOG_PREFIX, generation))
+}
+
+fn open_for_append(data_path: &Path, generation: u64) -> Result<File, Error> {
+    let file = OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(log_path(data_path, generation))?;
+
+    sync_dir(data_path)?;
+
+    Ok(file)
+}
+
+fn read_operations(path: &Path) -> Result<Vec<Operation>, Error> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+
+    let mut content = Vec::new();
+    file.read_to_end(&mut content)?;
+
+    // Last line without '\n' means that master crashed during append.
+    // Operation was not acknowledged to client, so it is safe to drop it.
+    let complete_len = content
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |position| position + 1);
+
+    if complete_len < content.len() {
+        warn!(
+            "Dropping incomplete operation at the end of log: {:?}",
+            path
+        );
+        file.set_len(complete_len as u64)?;
+        file.sync_all()?;
+    }
+
+    content[..complete_len]
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).map_err(|e| Error::new(ErrorKind::InvalidData, e)))
+        .collect()
 }
@@ -0,0 +1,143 @@
+use std::{sync::Arc, time::Duration};
+
+use common::chunk_server::{
+    master_service_client::MasterServiceClient, AcquireChunksRequest, ChunkData,
+};
+use tokio::{task::JoinSet, time::interval};
+use tonic::Request;
+use tracing::{error, info};
+
+use crate::storage::metadata::{CloneTask, Metadata};
+
+pub fn run_checkpoints(metadata: Arc<Metadata>, interval_secs: u64) {
+    let mut interval = interval(Duration::from_secs(interval_secs));
+
+    tokio::spawn(async move {
+        // First tick completes immediately, state was just recovered
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            info!("Starting metadata checkpoint");
+
+            let metadata = metadata.clone();
+
+            // Checkpoint writes to disk, so it should not block async workers
+            match tokio::task::spawn_blocking(move || metadata.checkpoint()).await {
+                Ok(Ok(())) => info!("Metadata checkpoint finished"),
+                Ok(Err(e)) => error!("Failed to write metadata checkpoint: {}", e),
+                Err(e) => error!("Metadata checkpoint task failed: {}", e),
+            }
+        }
+    });
+}
+
+// Purges files deleted longer than grace period ago
+pub fn run_garbage_collection(metadata: Arc<Metadata>, interval_secs: u64, grace_period: Duration) {
+    let mut interval = interval(Duration::from_secs(interval_secs));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let metadata = metadata.clone();
+
+            // Purges are logged, so it should not block async workers
+            match tokio::task::spawn_blocking(move || metadata.collect_garbage(grace_period)).await
+            {
+                Ok(Ok(purged)) if purged.is_empty() => {}
+                Ok(Ok(purged)) => info!("Garbage collection purged files: {:?}", purged),
+                Ok(Err(e)) => error!("Failed to collect garbage: {}", e),
+                Err(e) => error!("Garbage collection task failed: {}", e),
+            }
+        }
+    });
+}
+
+// Periodically checks heartbeats of chunk servers
+pub fn run_reaper(
+    metadata: Arc<Metadata>,
+    interval_secs: u64,
+    suspect_timeout: Duration,
+    dead_timeout: Duration,
+) {
+    let mut interval = interval(Duration::from_secs(interval_secs));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let dead = metadata.detect_dead_servers(suspect_timeout, dead_timeout);
+
+            if !dead.is_empty() {
+                info!("Chunk servers: {:?} are dead, replicas removed", dead);
+            }
+        }
+    });
+}
+
+// Copies under-replicated chunks to other servers. Next scan starts after all clones
+// from previous one finished, so at most max_concurrent_clones run in whole cluster.
+pub fn run_replication(metadata: Arc<Metadata>, interval_secs: u64, max_concurrent_clones: usize) {
+    let mut interval = interval(Duration::from_secs(interval_secs));
+
+    tokio::spawn(async move {
+        loop {
+            interval.tick().await;
+
+            let tasks = metadata.plan_replication(max_concurrent_clones);
+
+            if tasks.is_empty() {
+                continue;
+            }
+
+            info!("Starting re-replication of {} chunks", tasks.len());
+
+            let mut clones = JoinSet::new();
+
+            for task in tasks {
+                let metadata = metadata.clone();
+
+                clones.spawn(async move {
+                    match clone_chunk(&task).await {
+                        Ok(()) => {
+                            info!(
+                                "Chunk: {} cloned from: {} to: {}",
+                                task.chunk_handle, task.source, task.target
+                            );
+                            metadata.add_chunk_location(
+                                task.chunk_handle,
+                                &task.target,
+                                task.version,
+                            );
+                        }
+                        Err(e) => error!(
+                            "Failed to clone chunk: {} from: {} to: {}, because: {}",
+                            task.chunk_handle, task.source, task.target, e
+                        ),
+                    }
+                });
+            }
+
+            while clones.join_next().await.is_some() {}
+        }
+    });
+}
+
+// Target server copies chunk from source
+async fn clone_chunk(task: &CloneTask) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = MasterServiceClient::connect(format!("http://{}", task.target)).await?;
+
+    let request = Request::new(AcquireChunksRequest {
+        chunks_to_acquire: vec![ChunkData {
+            chunk_handle: task.chunk_handle,
+            address: task.source.clone(),
+            version: task.version,
+        }],
+    });
+
+    client.acquire_chunks(request).await?;
+
+    Ok(())
+}